@@ -0,0 +1,47 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use crdt::Storage;
+
+/// A 1 MB document of 80 character lines.
+fn document() -> Storage {
+    let line = format!("{}\n", "x".repeat(79));
+    line.repeat((1 << 20) / line.len()).chars().collect()
+}
+
+fn offsets(c: &mut Criterion) {
+    let storage = document();
+    let middle = storage.len() / 2;
+    let pos = storage.position_at(middle).unwrap().clone();
+
+    let mut group = c.benchmark_group("1 MB document");
+
+    group.bench_function("position_at (index)", |b| {
+        b.iter(|| storage.position_at(black_box(middle)))
+    });
+    group.bench_function("position_at (walk)", |b| {
+        b.iter(|| storage.characters(..).nth(black_box(middle)))
+    });
+
+    group.bench_function("offset_of (index)", |b| {
+        b.iter(|| storage.offset_of(black_box(&pos)))
+    });
+    group.bench_function("offset_of (walk)", |b| {
+        b.iter(|| {
+            storage
+                .characters(..)
+                .position(|(p, _)| p == black_box(&pos))
+        })
+    });
+
+    group.bench_function("line_start (index)", |b| {
+        b.iter(|| storage.line_start(black_box(middle / 80)))
+    });
+    group.bench_function("line_start (walk)", |b| {
+        b.iter(|| storage.lines(..).nth(black_box(middle / 80)))
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, offsets);
+criterion_main!(benches);
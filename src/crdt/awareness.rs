@@ -0,0 +1,164 @@
+use std::collections::BTreeMap;
+
+use crate::{Merge, Selection};
+
+/// Who is editing a document, and where: the presence of each site, shared alongside its operations.
+///
+/// Each site only updates its own [`Presence`], which replaces any older one elsewhere. Every
+/// update counts as a heartbeat; a peer that stops sending them is expired by each replica in its
+/// own time. Timestamps are whatever the application uses, in milliseconds say, as long as all of
+/// its peers agree.
+///
+/// Sites are identified by their UUIDs, rather than their ids, which can change as others are
+/// registered, and differ between replicas.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Awareness<M> {
+    pub(crate) peers: BTreeMap<u128, Presence<M>>,
+    expired: BTreeMap<u128, u64>, // the `clock` of each expired peer, so that it isn’t resurrected
+}
+
+/// The cursors and metadata — a name or colour, say — of a single site.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Presence<M> {
+    pub selections: Vec<Selection>,
+    pub metadata: M,
+    /// When the site last updated its presence.
+    pub heartbeat: u64,
+    pub(crate) clock: u64, // incremented by the site with each update; the latest wins
+}
+
+impl<M> Default for Awareness<M> {
+    fn default() -> Self {
+        Awareness {
+            peers: BTreeMap::new(),
+            expired: BTreeMap::new(),
+        }
+    }
+}
+
+impl<M> Awareness<M> {
+    pub fn get(&self, uuid: u128) -> Option<&Presence<M>> {
+        self.peers.get(&uuid)
+    }
+
+    /// Iterates over the presence of each site, by UUID.
+    pub fn iter(&self) -> impl Iterator<Item = (u128, &Presence<M>)> {
+        self.peers.iter().map(|(uuid, presence)| (*uuid, presence))
+    }
+
+    /// Returns the number of sites present.
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    /// Replaces the presence of the site identified by `uuid`, which should be this replica’s own.
+    pub fn update(&mut self, uuid: u128, selections: Vec<Selection>, metadata: M, now: u64) {
+        let clock = self.clock(uuid) + 1;
+        self.expired.remove(&uuid);
+        self.peers.insert(
+            uuid,
+            Presence {
+                selections,
+                metadata,
+                heartbeat: now,
+                clock,
+            },
+        );
+    }
+
+    /// Renews the presence of the site identified by `uuid`, which should be this replica’s own,
+    /// without changing it.
+    pub fn heartbeat(&mut self, uuid: u128, now: u64) {
+        if let Some(presence) = self.peers.get_mut(&uuid) {
+            presence.clock += 1;
+            presence.heartbeat = now;
+        }
+    }
+
+    /// Removes the sites that haven’t been heard from within `timeout` of `now`, returning their UUIDs.
+    pub fn expire(&mut self, now: u64, timeout: u64) -> Vec<u128> {
+        let stale: Vec<u128> = self
+            .iter()
+            .filter(|(_, presence)| presence.heartbeat.saturating_add(timeout) < now)
+            .map(|(uuid, _)| uuid)
+            .collect();
+
+        for uuid in &stale {
+            // SAFETY: `stale` was taken from the `peers`
+            let presence = self.peers.remove(uuid).unwrap();
+            self.expired.insert(*uuid, presence.clock);
+        }
+
+        stale
+    }
+
+    /// Returns the latest `clock` seen from the site identified by `uuid`.
+    fn clock(&self, uuid: u128) -> u64 {
+        match self.peers.get(&uuid) {
+            Some(presence) => presence.clock,
+            None => self.expired.get(&uuid).copied().unwrap_or_default(),
+        }
+    }
+}
+
+impl<M: Clone> Merge for Awareness<M> {
+    /// The latest presence of each site in either.
+    fn merge(&mut self, other: &Self) {
+        for (uuid, presence) in other.iter() {
+            if presence.clock > self.clock(uuid) {
+                self.expired.remove(&uuid);
+                self.peers.insert(uuid, presence.clone());
+            }
+        }
+    }
+}
+
+#[test]
+fn latest_presence_wins() {
+    use crate::{Cursor, Gravity, Storage};
+
+    let mut storage = Storage::with_uuid(1);
+    storage.extend("hello".chars());
+
+    let caret = |n| {
+        let cursor = Cursor::at(&storage, n, Gravity::Right).unwrap();
+        Selection {
+            anchor: cursor.clone(),
+            head: cursor,
+        }
+    };
+
+    let mut a = Awareness::default();
+    let mut b = Awareness::default();
+    a.update(1, vec![caret(0)], "alice", 100);
+    b.update(2, vec![caret(5)], "bob", 100);
+
+    b.merge(&a);
+    a.update(1, vec![caret(3)], "alice", 200);
+    a.merge(&b); // b’s copy of a is older than a’s own
+    b.merge(&a);
+
+    assert_eq!(a, b);
+    assert_eq!(a.len(), 2);
+    assert_eq!(a.get(1).unwrap().selections, [caret(3)]);
+
+    // b stops sending heartbeats, and is expired…
+    a.heartbeat(1, 2_000);
+    assert_eq!(a.expire(2_500, 1_000), [2]);
+    assert_eq!(a.len(), 1);
+
+    // …and stays that way, however often its last presence is gossiped
+    let mut c = Awareness::default();
+    c.merge(&b);
+    a.merge(&c);
+    assert!(a.get(2).is_none());
+
+    // until it is heard from again
+    b.update(2, vec![], "bob", 2_600);
+    a.merge(&b);
+    assert_eq!(a.get(2).map(|presence| presence.heartbeat), Some(2_600));
+}
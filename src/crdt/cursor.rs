@@ -0,0 +1,191 @@
+use std::ops::Bound::{Excluded, Included, Unbounded};
+use std::ops::Range;
+
+use crate::{Element, Position, Storage, Unit};
+
+/// Which of its neighbours a [`Cursor`] sticks to, and so which side of it an insert at the
+/// cursor lands on.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_crate::Serialize, serde_crate::Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub enum Gravity {
+    /// Sticks to the element before it; an insert at the cursor lands after it.
+    Left,
+    /// Sticks to the element after it; an insert at the cursor lands before it — as it does
+    /// when typing at a caret.
+    #[default]
+    Right,
+}
+
+/// A place between two elements that stays put while the document is edited around it.
+///
+/// Rather than an offset, which any edit before it would invalidate, a cursor holds the
+/// [`Position`] of the element it sticks to. If that element is removed, the cursor falls back
+/// to its nearest surviving neighbour; as removed positions are still ordered with respect to
+/// the remaining ones.
+///
+/// Along with the position goes the `uuid` of the site that made it; so the cursor stays put
+/// should that site be given a new id, and means the same at a replica that knows it by another.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cursor {
+    pub pos: Position,
+    pub uuid: u128,
+    pub gravity: Gravity,
+}
+
+/// The range between an `anchor`, where it was started, and a `head`, where it was extended to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Selection {
+    pub anchor: Cursor,
+    pub head: Cursor,
+}
+
+impl Cursor {
+    /// Creates a cursor before the element at `index`, or at the end of the document.
+    ///
+    /// Returns `None` if `index` is past the end of the document.
+    pub fn at<T: Element>(storage: &Storage<T>, index: usize, gravity: Gravity) -> Option<Cursor> {
+        let pos = match gravity {
+            Gravity::Left if index == 0 => storage.newlines.first()?,
+            Gravity::Left if index <= storage.len() => storage.position_at(index - 1)?,
+            Gravity::Left => return None,
+            Gravity::Right => storage.position_at(index)?,
+        };
+
+        Some(Cursor {
+            pos: pos.clone(),
+            uuid: storage.sites().uuid(pos.site_id())?,
+            gravity,
+        })
+    }
+
+    /// Returns the index of the element after the cursor — or the length of the document,
+    /// at its end.
+    pub fn offset<T: Element>(&self, storage: &Storage<T>) -> usize {
+        let pos = storage.local(&self.pos, self.uuid);
+        let before = storage.index.rank(&pos).chars;
+
+        match self.gravity {
+            Gravity::Left => before + storage.elements.contains_key(&pos) as usize,
+            Gravity::Right => before,
+        }
+    }
+
+    /// Returns the position of the element after the cursor — or [`Position::last()`], at the
+    /// end of the document — which can be passed to [`Storage::insert()`].
+    pub fn position<'a, T: Element>(&self, storage: &'a Storage<T>) -> &'a Position {
+        let pos = storage.local(&self.pos, self.uuid);
+        let bound = match self.gravity {
+            Gravity::Left => Excluded(&pos),
+            Gravity::Right => Included(&pos),
+        };
+
+        storage
+            .elements
+            .range((bound, Unbounded))
+            .map(|(pos, _)| pos)
+            .next()
+            .unwrap_or(storage.last())
+    }
+
+    /// Returns the (zero-based) line and column of the cursor.
+    pub fn line_col(&self, storage: &Storage, unit: Unit) -> (usize, usize) {
+        // SAFETY: `position()` is always in the document, or the last
+        storage.line_col_of(self.position(storage), unit).unwrap()
+    }
+}
+
+impl Selection {
+    /// Creates a selection from the element at `anchor` to the one at `head`.
+    ///
+    /// Inserts at either end aren’t taken into it, while those at a collapsed selection
+    /// land before it. Returns `None` if either is past the end of the document.
+    pub fn at<T: Element>(storage: &Storage<T>, anchor: usize, head: usize) -> Option<Selection> {
+        let gravity = |n, other| match n > other {
+            true => Gravity::Left,
+            false => Gravity::Right,
+        };
+
+        Some(Selection {
+            anchor: Cursor::at(storage, anchor, gravity(anchor, head))?,
+            head: Cursor::at(storage, head, gravity(head, anchor))?,
+        })
+    }
+
+    /// Returns the indices of the selected elements, whichever way it was made.
+    pub fn range<T: Element>(&self, storage: &Storage<T>) -> Range<usize> {
+        let (anchor, head) = (self.anchor.offset(storage), self.head.offset(storage));
+        Ord::min(anchor, head)..Ord::max(anchor, head)
+    }
+
+    pub fn is_empty<T: Element>(&self, storage: &Storage<T>) -> bool {
+        self.range(storage).is_empty()
+    }
+}
+
+#[test]
+fn stable_cursors() {
+    let mut a = Storage::with_uuid(1);
+    let mut b = Storage::with_uuid(2);
+
+    for op in a.append("hello world".chars()) {
+        b.apply(&op);
+    }
+
+    let caret = Cursor::at(&a, 5, Gravity::Right).unwrap();
+    let left = Cursor::at(&a, 5, Gravity::Left).unwrap();
+    let selection = Selection::at(&a, 11, 6).unwrap(); // “world”, made backwards
+
+    // a remote insert before them, and another at them
+    for op in b.insert_str_at(0, ">> ") {
+        a.apply(&op);
+    }
+    for op in b.insert_str_at(8, ",") {
+        a.apply(&op);
+    }
+
+    assert_eq!(a.string(..), ">> hello, world");
+    assert_eq!(caret.offset(&a), 9);
+    assert_eq!(left.offset(&a), 8);
+    assert_eq!(a.string(..).get(selection.range(&a)), Some("world"));
+    assert_eq!(caret.line_col(&a, Unit::Chars), (0, 9));
+
+    // removing the character a cursor sticks to leaves it beside the next one that survives
+    let _ = a.remove_range(6..9);
+    assert_eq!(a.string(..), ">> hel world");
+    assert_eq!(caret.offset(&a), 6);
+    assert_eq!(left.offset(&a), 6);
+    assert_eq!(a.get(caret.position(&a)), Some(&' '));
+
+    // and at the end of the document
+    let end = Cursor::at(&a, a.len(), Gravity::Left).unwrap();
+    let _ = a.remove_range(4..a.len());
+    assert_eq!(end.offset(&a), 4);
+    assert_eq!(end.position(&a), &Position::last());
+    assert!(selection.is_empty(&a));
+}
+
+#[test]
+fn renumbered_sites() {
+    use crate::Merge;
+
+    // two UUIDs given the same site id, until each learns of the other
+    let mut a = Storage::with_uuid(1 + u16::MAX as u128);
+    let mut b = Storage::with_uuid(1);
+    a.append("hello".chars());
+    let caret = Cursor::at(&a, 3, Gravity::Right).unwrap();
+
+    // a’s site moves out of the way of b’s, its positions along with it; the cursor doesn’t mind
+    b.append("!".chars());
+    a.merge(&b);
+    assert_ne!(a.sites().site_id(caret.uuid), Some(caret.pos.site_id()));
+    assert_eq!(a.get(caret.position(&a)), Some(&'l'));
+
+    // and means the same at b, which learns of a’s site by its new id
+    b.merge(&a);
+    assert_eq!(caret.offset(&b), caret.offset(&a));
+    assert_eq!(b.get(caret.position(&b)), Some(&'l'));
+}
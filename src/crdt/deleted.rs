@@ -0,0 +1,258 @@
+//! The set of every [`Position`] that has been removed from a document.
+//!
+//! Deleted positions must be remembered so that an insert arriving after its own delete —
+//! from a replica that hadn’t yet seen one or the other — isn’t resurrected. Rather than keep
+//! each removed `Position` whole, they are grouped by the site and clock that generated them
+//! and, as a run of inserts shares all but the last level of its paths, by that common prefix;
+//! leaving just the ranges of last-level values. Deleting a run typed with a single `clock` then
+//! costs a single entry.
+//!
+//! Each range also keeps the edits that deleted it, so that [`Merge`](crate::Merge) and
+//! [`Storage::operations_since()`](crate::Storage::operations_since) can replay the deletes
+//! without a second, uncompacted, record of them.
+
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+
+use tinyvec::TinyVec;
+
+use crate::crdt::pos::path::Builder;
+use crate::crdt::sites::Renames;
+use crate::{Dot, Position};
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Deleted {
+    runs: BTreeMap<Run, Ranges>,
+    len: usize,
+}
+
+/// The site, clock and path prefix shared by a run of positions.
+pub(crate) type Run = (u32, u64, Builder);
+
+/// Inclusive ranges of last-level values, keyed by their start; each with the edits that deleted it.
+type Ranges = BTreeMap<u32, (u32, Dots)>;
+
+/// Usually just the one; but an element can be removed concurrently at several sites.
+pub(crate) type Dots = TinyVec<[Dot; 1]>;
+
+impl Deleted {
+    /// Returns the number of deleted positions.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn contains(&self, pos: &Position) -> bool {
+        let Some((run, value)) = Self::split(pos) else {
+            return false;
+        };
+
+        self.runs
+            .get(&run)
+            .and_then(|ranges| ranges.range(..=value).next_back())
+            .is_some_and(|(_, (end, _))| value <= *end)
+    }
+
+    /// Records `pos` as deleted by the edit identified by `dot`, returning whether it is new to the set.
+    pub fn insert(&mut self, pos: &Position, dot: Dot) -> bool {
+        let Some((run, value)) = Self::split(pos) else {
+            return false;
+        };
+
+        let ranges = self.runs.entry(run).or_default();
+        let existing = ranges
+            .range(..=value)
+            .next_back()
+            .filter(|(_, (end, _))| value <= *end)
+            .map(|(start, _)| *start);
+
+        let new = match existing {
+            Some(start) => {
+                // SAFETY: `start` was just found in the `ranges`
+                let (end, dots) = ranges.remove(&start).unwrap();
+                if dots.contains(&dot) {
+                    ranges.insert(start, (end, dots));
+                    return false;
+                }
+
+                // split the range about `value`, which gains another dot
+                if start < value {
+                    ranges.insert(start, (value - 1, dots.clone()));
+                }
+                if value < end {
+                    ranges.insert(value + 1, (end, dots.clone()));
+                }
+
+                let mut dots = dots;
+                dots.push(dot);
+                dots.sort_unstable();
+                ranges.insert(value, (value, dots));
+                false
+            }
+            None => {
+                ranges.insert(value, (value, TinyVec::from([dot])));
+                self.len += 1;
+                true
+            }
+        };
+
+        Self::coalesce(ranges, value);
+        new
+    }
+
+    /// Records the positions of a whole range of a `run` as deleted by the `dots`; returning
+    /// `false`, and recording nothing, should any of them already be.
+    pub fn insert_range(&mut self, run: Run, values: RangeInclusive<u32>, dots: Dots) -> bool {
+        let (start, end) = values.into_inner();
+        let ranges = self.runs.entry(run).or_default();
+
+        let overlaps = ranges
+            .range(..=end)
+            .next_back()
+            .is_some_and(|(_, (last, _))| start <= *last);
+        if start == 0 || end < start || dots.is_empty() || overlaps {
+            return false;
+        }
+
+        ranges.insert(start, (end, dots));
+        Self::coalesce(ranges, start);
+        self.len += (end - start) as usize + 1;
+        true
+    }
+
+    /// Iterates over the deleted positions, along with each edit that deleted them.
+    pub fn iter(&self) -> impl Iterator<Item = (Position, Dot)> + '_ {
+        self.runs().flat_map(|(run, values, dots)| {
+            values.flat_map(move |value| {
+                let pos = Self::join(run, value);
+                dots.iter().map(move |dot| (pos.clone(), *dot))
+            })
+        })
+    }
+
+    /// Iterates over the ranges of each run, along with the edits that deleted them.
+    pub fn runs(&self) -> impl Iterator<Item = (&Run, RangeInclusive<u32>, &Dots)> + '_ {
+        self.runs.iter().flat_map(|(run, ranges)| {
+            ranges
+                .iter()
+                .map(move |(start, (end, dots))| (run, *start..=*end, dots))
+        })
+    }
+
+    /// Returns this set, with the sites in `renames` renumbered.
+    pub fn renamed(&self, renames: &Renames) -> Deleted {
+        let mut new = Deleted::default();
+        for ((site, clock, prefix), values, dots) in self.runs() {
+            let site = renames.get(site).copied().unwrap_or(*site);
+            let dots = dots.iter().map(|dot| dot.renamed(renames)).collect();
+            new.insert_range((site, *clock, prefix.clone()), values, dots);
+        }
+
+        new
+    }
+
+    /// Returns the position within a `run` whose last-level value is `value`.
+    pub fn join((site, clock, prefix): &Run, value: u32) -> Position {
+        let mut path = prefix.clone();
+        path.push(value);
+        Position::new(*site, *clock, &path)
+    }
+
+    /// Splits `pos` into its run — site, clock and path prefix — and its last-level value.
+    pub fn split(pos: &Position) -> Option<(Run, u32)> {
+        let (value, prefix) = pos.path().split_last()?;
+        Some(((pos.site_id(), pos.clock(), Builder::from(prefix)), *value))
+    }
+
+    /// Joins the range that holds `value` with those either side of it, deleted by the same edits.
+    fn coalesce(ranges: &mut Ranges, value: u32) {
+        // SAFETY: a range holding `value` has just been inserted
+        let (mut start, (mut end, dots)) = ranges
+            .range(..=value)
+            .next_back()
+            .map(|(start, range)| (*start, range.clone()))
+            .unwrap();
+
+        if let Some((&prev, (prev_end, prev_dots))) = ranges.range(..start).next_back() {
+            if prev_end.checked_add(1) == Some(start) && *prev_dots == dots {
+                ranges.remove(&start);
+                start = prev;
+            }
+        }
+
+        if let Some(next) = end.checked_add(1) {
+            if ranges
+                .get(&next)
+                .is_some_and(|(_, next_dots)| *next_dots == dots)
+            {
+                // SAFETY: just checked
+                end = ranges.remove(&next).unwrap().0;
+            }
+        }
+
+        ranges.insert(start, (end, dots));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+
+    use quickcheck_macros::quickcheck;
+
+    use super::*;
+
+    #[quickcheck]
+    fn matches_a_set(positions: Vec<(u8, u8, u8)>) {
+        let mut deleted = Deleted::default();
+        let mut model = BTreeSet::new();
+
+        for (clock, prefix, value) in positions {
+            let path = [prefix as u32 + 2, value as u32 + 2];
+            let pos = Position::new(1, clock as u64 % 4, &path);
+            assert_eq!(deleted.insert(&pos, dot(1)), model.insert(pos));
+        }
+
+        assert_eq!(deleted.len(), model.len());
+        assert_eq!(
+            deleted.iter().map(|(pos, _)| pos).collect::<BTreeSet<_>>(),
+            model
+        );
+        for pos in &model {
+            assert!(deleted.contains(pos));
+        }
+    }
+
+    #[test]
+    fn runs_are_compacted() {
+        let mut deleted = Deleted::default();
+        for value in [4, 2, 3, 6, 5] {
+            deleted.insert(&Position::new(1, 1, &[7, value]), dot(1));
+        }
+
+        assert_eq!(deleted.len(), 5);
+        assert_eq!(deleted.runs().count(), 1);
+        assert!(!deleted.contains(&Position::new(1, 1, &[7, 7])));
+        assert!(!deleted.contains(&Position::new(1, 2, &[7, 4])));
+
+        // removed again, concurrently, the range is split around it…
+        assert!(!deleted.insert(&Position::new(1, 1, &[7, 4]), dot(2)));
+        assert_eq!(deleted.runs().count(), 3);
+        assert_eq!(deleted.len(), 5);
+        assert_eq!(deleted.iter().count(), 6);
+
+        // …and joined up again, as its neighbours are too
+        for value in [2, 3, 5, 6] {
+            deleted.insert(&Position::new(1, 1, &[7, value]), dot(2));
+        }
+        assert_eq!(deleted.runs().count(), 1);
+        assert_eq!(deleted.iter().count(), 10);
+    }
+
+    fn dot(clock: u64) -> Dot {
+        Dot { site: 2, clock }
+    }
+}
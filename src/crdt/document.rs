@@ -0,0 +1,748 @@
+//! A JSON document that can be edited collaboratively as a whole.
+//!
+//! Each kind of JSON value is replicated in its own way: an object is a map whose fields are
+//! assigned last-writer-wins, as those of a [`Map`](crate::Map) are; an array is a [`Storage`] of
+//! items, placed with LSEQ [`Position`]s as characters are; a string is a [`Storage`] text, edited
+//! character by character; and anything else is a register, replaced outright. Each of them is
+//! kept in a `Storage` of its own, with its own clock, that knows of the sites the document did
+//! when it was made; so a [`Merge`] of two documents converges as one of texts does.
+//!
+//! A new object, array or string is described by the operations that make its fields, items or
+//! characters; so that it is made of the same positions, by the same edits, at every replica.
+//!
+//! Edits address a value by the keys of the objects, and the positions of the list items, that
+//! lead to it rather than by index, which concurrent inserts and removals would shift.
+
+use std::collections::BTreeMap;
+
+use serde_json::{Number, Value};
+
+use crate::crdt::index::Metric;
+use crate::crdt::map::{Assign, Fields};
+use crate::{Assignment, Element, Merge, Operation, Position, SiteRegistry, Storage};
+
+/// A replicated JSON document, whose root is an object.
+pub struct Document {
+    root: Node, // always an object
+    uuid: u128,
+}
+
+/// A value within a [`Document`].
+pub enum Node {
+    Scalar(Scalar),
+    Text(Box<Storage>),
+    List(List),
+    Object(Object),
+}
+
+/// A JSON value that is replaced, rather than edited.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Scalar {
+    Null,
+    Bool(bool),
+    Int(i64),
+    /// A number too large for an `Int`.
+    Uint(u64),
+    Float(f64),
+}
+
+/// A replicated JSON array.
+pub struct List {
+    items: Storage<Init>,            // how each item was made…
+    nodes: BTreeMap<Position, Node>, // …and what it has become since
+}
+
+/// A replicated JSON object.
+pub struct Object {
+    fields: Fields<String, Init, Node>,
+}
+
+/// A step along the path to a value: the key of an object’s field or the index of a list’s item.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Segment<'a> {
+    Key(&'a str),
+    Index(usize),
+}
+
+/// A step along the path to a value, that concurrent edits don’t shift.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Key {
+    Field(String),
+    /// The position of a list item, along with the UUID of the site that made it; as another
+    /// replica may know that site by a different id.
+    Item {
+        pos: Position,
+        uuid: u128,
+    },
+}
+
+/// An edit, made at one site, of the value at the end of its `target` path.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DocOperation {
+    pub target: Vec<Key>,
+    pub edit: Edit,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Edit {
+    /// Assigns the value of — or removes — a field of an object.
+    Field(Operation<Assignment<String, Init>>),
+    /// Inserts, or removes, an item of a list.
+    Item(Operation<Init>),
+    /// Edits a text.
+    Text(Operation),
+}
+
+/// A new value, along with the operations that make its characters, list items or fields.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Init {
+    Scalar(Scalar),
+    Text(Vec<Operation>),
+    List(Vec<Operation<Init>>),
+    Object(Vec<Operation<Assignment<String, Init>>>),
+}
+
+impl Element for Init {}
+
+impl<'a> From<&'a str> for Segment<'a> {
+    fn from(key: &'a str) -> Self {
+        Segment::Key(key)
+    }
+}
+
+impl From<usize> for Segment<'_> {
+    fn from(index: usize) -> Self {
+        Segment::Index(index)
+    }
+}
+
+impl Default for Document {
+    fn default() -> Self {
+        Self::with_uuid(crate::crdt::sites::uuid())
+    }
+}
+
+impl Document {
+    /// Creates a replica, of an empty object, for the site identified by `uuid`.
+    pub fn with_uuid(uuid: u128) -> Self {
+        Document {
+            root: Node::Object(Object::new(&SiteRegistry::default(), uuid)),
+            uuid,
+        }
+    }
+
+    /// Creates a document holding `value`; or returns `None` should it not be an object.
+    ///
+    /// Two documents made from the same value are unrelated: a replica of another is made by
+    /// applying its operations to, or merging it with, an empty document.
+    pub fn from_value(value: &Value) -> Option<Self> {
+        let mut document = Document::default();
+        value.is_object().then(|| {
+            document.assign(value);
+            document
+        })
+    }
+
+    /// Returns the value at the end of `path`: `doc.get(&["todos".into(), 3.into(), "title".into()])`.
+    pub fn get(&self, path: &[Segment]) -> Option<&Node> {
+        path.iter().try_fold(&self.root, |node, segment| {
+            node.child(&Self::key(node, *segment)?)
+        })
+    }
+
+    /// Assigns `value` to the field at the end of `path`, returning the [`DocOperation`]s needed
+    /// to replicate it.
+    ///
+    /// Returns no operations if `path` doesn’t end with the key of a field of an existing object.
+    pub fn set(&mut self, path: &[Segment], value: &Value) -> Vec<DocOperation> {
+        let Some((Segment::Key(key), parent)) = path.split_last() else {
+            return Vec::new();
+        };
+        let Some(target) = self.resolve(parent) else {
+            return Vec::new();
+        };
+
+        let uuid = self.uuid;
+        let Some(Node::Object(object)) = self.node_mut(&target) else {
+            return Vec::new();
+        };
+
+        let value = init(value, object.fields.sites(), uuid);
+        let ops = object.fields.assign(key.to_string(), Some(value));
+        Self::edits(&target, ops, Edit::Field)
+    }
+
+    /// Inserts `value` so that it becomes the item at `index` of the list at `path`, returning
+    /// the [`DocOperation`] needed to replicate it.
+    pub fn insert(
+        &mut self,
+        path: &[Segment],
+        index: usize,
+        value: &Value,
+    ) -> Option<DocOperation> {
+        let target = self.resolve(path)?;
+        let uuid = self.uuid;
+        let Some(Node::List(list)) = self.node_mut(&target) else {
+            return None;
+        };
+
+        let value = init(value, list.items.sites(), uuid);
+        let op = list.items.insert_at(index, value)?;
+        list.update(&op);
+
+        Some(DocOperation {
+            target,
+            edit: Edit::Item(op),
+        })
+    }
+
+    /// Removes the field, or list item, at the end of `path`, returning the [`DocOperation`]s
+    /// needed to replicate it.
+    pub fn remove(&mut self, path: &[Segment]) -> Vec<DocOperation> {
+        let Some(mut target) = self.get(path).and_then(|_| self.resolve(path)) else {
+            return Vec::new();
+        };
+
+        let key = target.pop();
+        match (self.node_mut(&target), key) {
+            (Some(Node::Object(object)), Some(Key::Field(key))) => {
+                let ops = object.fields.assign(key, None);
+                Self::edits(&target, ops, Edit::Field)
+            }
+            (Some(Node::List(list)), Some(Key::Item { pos, uuid })) => {
+                let pos = list.local(&pos, uuid);
+                let ops = Vec::from_iter(pos.and_then(|pos| list.items.remove(&pos)));
+                ops.iter().for_each(|op| list.update(op));
+                Self::edits(&target, ops, Edit::Item)
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Edits the text at `path` with `f`, returning the [`DocOperation`]s needed to replicate it.
+    pub fn edit_text(
+        &mut self,
+        path: &[Segment],
+        f: impl FnOnce(&mut Storage) -> Vec<Operation>,
+    ) -> Vec<DocOperation> {
+        let Some(target) = self.resolve(path) else {
+            return Vec::new();
+        };
+        let Some(Node::Text(text)) = self.node_mut(&target) else {
+            return Vec::new();
+        };
+
+        Self::edits(&target, f(text), Edit::Text)
+    }
+
+    /// Assigns each field of `value`, an object, to the root — and removes those it doesn’t have —
+    /// returning the [`DocOperation`]s needed to replicate it.
+    pub fn assign(&mut self, value: &Value) -> Vec<DocOperation> {
+        let Value::Object(fields) = value else {
+            return Vec::new();
+        };
+
+        let Node::Object(root) = &self.root else {
+            unreachable!("the root is always an object");
+        };
+
+        let removed: Vec<String> = root
+            .iter()
+            .map(|(key, _)| key.to_string())
+            .filter(|key| !fields.contains_key(key))
+            .collect();
+
+        let removals = removed
+            .iter()
+            .flat_map(|key| self.remove(&[Segment::Key(key)]))
+            .collect::<Vec<_>>();
+
+        let assignments = fields
+            .iter()
+            .flat_map(|(key, value)| self.set(&[Segment::Key(key)], value))
+            .collect::<Vec<_>>();
+
+        [removals, assignments].concat()
+    }
+
+    /// Integrates a [`DocOperation`] generated by another replica, returning whether the document
+    /// changed.
+    ///
+    /// Edits within a value that has since been removed, or replaced, are ignored.
+    pub fn apply(&mut self, op: &DocOperation) -> bool {
+        match (self.node_mut(&op.target), &op.edit) {
+            (Some(Node::Object(object)), Edit::Field(op)) => object.fields.apply(op),
+            (Some(Node::List(list)), Edit::Item(op)) => list.apply(op),
+            (Some(Node::Text(text)), Edit::Text(op)) => text.apply(op),
+            _ => false,
+        }
+    }
+
+    /// Returns the document as a JSON value.
+    pub fn to_value(&self) -> Value {
+        self.root.to_value()
+    }
+
+    /// Wraps each of the `ops`, made by the value at the end of the `target` path.
+    fn edits<O>(target: &[Key], ops: Vec<O>, edit: impl Fn(O) -> Edit) -> Vec<DocOperation> {
+        ops.into_iter()
+            .map(|op| DocOperation {
+                target: target.to_vec(),
+                edit: edit(op),
+            })
+            .collect()
+    }
+
+    /// Returns the stable keys of `path`.
+    fn resolve(&self, path: &[Segment]) -> Option<Vec<Key>> {
+        let mut keys = Vec::new();
+        let mut node = &self.root;
+
+        for segment in path {
+            let key = Self::key(node, *segment)?;
+            node = node.child(&key)?;
+            keys.push(key);
+        }
+
+        Some(keys)
+    }
+
+    /// Returns the stable key of `segment` within `node`.
+    fn key(node: &Node, segment: Segment) -> Option<Key> {
+        match (node, segment) {
+            (Node::Object(_), Segment::Key(key)) => Some(Key::Field(key.to_string())),
+            (Node::List(list), Segment::Index(index)) => {
+                let pos = list.position_at(index)?;
+                Some(Key::Item {
+                    pos: pos.clone(),
+                    uuid: list.items.sites().uuid(pos.site_id())?,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the value at the end of the `target` path.
+    fn node_mut(&mut self, target: &[Key]) -> Option<&mut Node> {
+        target
+            .iter()
+            .try_fold(&mut self.root, |node, key| match (node, key) {
+                (Node::Object(object), Key::Field(key)) => object.fields.get_mut(key),
+                (Node::List(list), Key::Item { pos, uuid }) => {
+                    let pos = list.local(pos, *uuid)?;
+                    list.nodes.get_mut(&pos)
+                }
+                _ => None,
+            })
+    }
+}
+
+impl Merge for Document {
+    /// Both documents, each value merged with that made by the same edit in the other.
+    fn merge(&mut self, other: &Self) {
+        self.root.merge(&other.root);
+    }
+}
+
+/// Describes `value` as a new value, made by the replica identified by `uuid`; its lists, objects
+/// and texts knowing of the same `sites` as the one it is assigned to, or inserted into.
+fn init(value: &Value, sites: &SiteRegistry, uuid: u128) -> Init {
+    match value {
+        Value::Null => Init::Scalar(Scalar::Null),
+        Value::Bool(bool) => Init::Scalar(Scalar::Bool(*bool)),
+        Value::Number(n) => Init::Scalar(match (n.as_i64(), n.as_u64()) {
+            (Some(n), _) => Scalar::Int(n),
+            (None, Some(n)) => Scalar::Uint(n),
+            (None, None) => Scalar::Float(n.as_f64().unwrap_or_default()),
+        }),
+        Value::String(str) => {
+            let mut text = Storage::with_sites(sites, uuid);
+            Init::Text(text.append(str.chars()))
+        }
+        Value::Array(items) => {
+            let mut list = Storage::with_sites(sites, uuid);
+            Init::List(list.append(items.iter().map(|item| init(item, sites, uuid))))
+        }
+        Value::Object(fields) => {
+            let mut assignments = Storage::with_sites(sites, uuid);
+            Init::Object(
+                assignments.append(fields.iter().map(|(key, value)| Assignment {
+                    key: key.clone(),
+                    value: Some(init(value, sites, uuid)),
+                })),
+            )
+        }
+    }
+}
+
+impl Assign for Node {
+    type Init = Init;
+
+    fn build(init: &Init, sites: &SiteRegistry, uuid: u128) -> Self {
+        match init {
+            Init::Scalar(scalar) => Node::Scalar(scalar.clone()),
+            Init::Text(ops) => {
+                let mut text = Storage::with_sites(sites, uuid);
+                for op in ops {
+                    text.apply(op);
+                }
+
+                Node::Text(Box::new(text))
+            }
+            Init::List(ops) => {
+                let mut list = List::new(sites, uuid);
+                for op in ops {
+                    list.apply(op);
+                }
+
+                Node::List(list)
+            }
+            Init::Object(ops) => {
+                let mut object = Object::new(sites, uuid);
+                for op in ops {
+                    object.fields.apply(op);
+                }
+
+                Node::Object(object)
+            }
+        }
+    }
+
+    fn merge(&mut self, other: &Self) {
+        match (self, other) {
+            (Node::Text(text), Node::Text(theirs)) => text.merge(theirs),
+            (Node::List(list), Node::List(theirs)) => list.merge(theirs),
+            (Node::Object(object), Node::Object(theirs)) => object.fields.merge(&theirs.fields),
+            _ => {} // a scalar is never changed, only replaced
+        }
+    }
+}
+
+impl Node {
+    fn child(&self, key: &Key) -> Option<&Node> {
+        match (self, key) {
+            (Node::Object(object), Key::Field(key)) => object.get(key),
+            (Node::List(list), Key::Item { pos, uuid }) => list.nodes.get(&list.local(pos, *uuid)?),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as JSON.
+    pub fn to_value(&self) -> Value {
+        match self {
+            Node::Scalar(Scalar::Null) => Value::Null,
+            Node::Scalar(Scalar::Bool(bool)) => Value::Bool(*bool),
+            Node::Scalar(Scalar::Int(n)) => Value::from(*n),
+            Node::Scalar(Scalar::Uint(n)) => Value::from(*n),
+            Node::Scalar(Scalar::Float(n)) => {
+                Number::from_f64(*n).map_or(Value::Null, Value::Number)
+            }
+            Node::Text(text) => Value::String(text.string(..)),
+            Node::List(list) => Value::Array(list.iter().map(Node::to_value).collect()),
+            Node::Object(object) => object.to_value(),
+        }
+    }
+}
+
+impl List {
+    fn new(sites: &SiteRegistry, uuid: u128) -> Self {
+        List {
+            items: Storage::with_sites(sites, uuid),
+            nodes: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the number of items.
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Node> {
+        self.nodes.get(self.position_at(index)?)
+    }
+
+    /// Iterates over the items, in order.
+    pub fn iter(&self) -> impl Iterator<Item = &Node> {
+        self.nodes.values()
+    }
+
+    fn position_at(&self, index: usize) -> Option<&Position> {
+        self.items.index.select(Metric::Chars, index)
+    }
+
+    /// Returns `pos`, made by the site identified by `uuid`, with the id that site has here.
+    fn local(&self, pos: &Position, uuid: u128) -> Option<Position> {
+        match self.items.sites().site_id(uuid)? {
+            site if site == pos.site_id() => Some(pos.clone()),
+            site => Some(Position::new(site, pos.clock(), pos.path())),
+        }
+    }
+
+    fn apply(&mut self, op: &Operation<Init>) -> bool {
+        let Ok((op, renames)) = self.items.localize(op) else {
+            return false;
+        };
+
+        // a site it mentions may have moved another, that inserted an item, out of the way
+        if !renames.is_empty() {
+            self.nodes = std::mem::take(&mut self.nodes)
+                .into_iter()
+                .map(|(pos, node)| (pos.renamed(&renames), node))
+                .collect();
+        }
+
+        let changed = self.items.integrate(&op).is_ok();
+        if changed {
+            self.update(&op);
+        }
+
+        changed
+    }
+
+    /// Makes, or removes, the item that `op` inserted, or removed.
+    fn update(&mut self, op: &Operation<Init>) {
+        match op {
+            Operation::Insert { pos, value, .. } => {
+                let node = Node::build(value, self.items.sites(), self.items.uuid());
+                self.nodes.insert(pos.clone(), node);
+            }
+            Operation::Delete { pos, .. } => {
+                self.nodes.remove(pos);
+            }
+        }
+    }
+
+    /// The items of both; each merged with that made by the same insert in the other.
+    fn merge(&mut self, other: &List) {
+        let sites = self.items.sites().clone();
+        self.items.merge(&other.items);
+
+        // the sites of either may have been renumbered, their positions along with them
+        let mut nodes: BTreeMap<_, _> = std::mem::take(&mut self.nodes)
+            .into_iter()
+            .map(|(pos, node)| (self.items.sites().translate(&pos, &sites), node))
+            .collect();
+        let theirs: BTreeMap<_, _> = other
+            .nodes
+            .iter()
+            .map(|(pos, node)| (self.items.sites().translate(pos, other.items.sites()), node))
+            .collect();
+
+        for (pos, init) in self.items.elements(..) {
+            let mut node = nodes
+                .remove(pos)
+                .unwrap_or_else(|| Node::build(init, self.items.sites(), self.items.uuid()));
+            if let Some(theirs) = theirs.get(pos) {
+                node.merge(theirs);
+            }
+
+            self.nodes.insert(pos.clone(), node);
+        }
+    }
+}
+
+impl Object {
+    fn new(sites: &SiteRegistry, uuid: u128) -> Self {
+        Object {
+            fields: Fields::new(sites, uuid),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Node> {
+        self.fields.get(key)
+    }
+
+    /// Iterates over the fields, by key.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Node)> {
+        self.fields.iter().map(|(key, node)| (key.as_str(), node))
+    }
+
+    fn to_value(&self) -> Value {
+        let fields = self
+            .iter()
+            .map(|(key, node)| (key.to_string(), node.to_value()));
+        Value::Object(fields.collect())
+    }
+}
+
+impl From<&Document> for Value {
+    fn from(document: &Document) -> Self {
+        document.to_value()
+    }
+}
+
+impl TryFrom<Value> for Document {
+    type Error = Value;
+
+    /// As [`Document::from_value()`]; returning the `value` should it not be an object.
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        Document::from_value(&value).ok_or(value)
+    }
+}
+
+#[test]
+fn collaborative_todos() {
+    use serde_json::json;
+
+    let mut a = Document::with_uuid(1);
+    let mut b = Document::with_uuid(2);
+
+    let todos = json!({
+        "title": "Groceries",
+        "todos": [
+            { "title": "milk", "done": false },
+            { "title": "eggs", "done": true },
+        ],
+    });
+
+    for op in a.assign(&todos) {
+        b.apply(&op);
+    }
+    assert_eq!(b.to_value(), todos);
+
+    // concurrently: a adds a todo, while b ticks off and retitles the first one
+    let added = a.insert(
+        &["todos".into()],
+        0,
+        &json!({ "title": "bread", "done": false }),
+    );
+    let done = b.set(&["todos".into(), 0.into(), "done".into()], &json!(true));
+    let retitled = b.edit_text(&["todos".into(), 0.into(), "title".into()], |text| {
+        text.insert_str_at(4, " (oat)")
+    });
+
+    b.apply(&added.unwrap());
+    for op in done.into_iter().chain(retitled) {
+        a.apply(&op);
+    }
+
+    let expected = json!({
+        "title": "Groceries",
+        "todos": [
+            { "title": "bread", "done": false },
+            { "title": "milk (oat)", "done": true },
+            { "title": "eggs", "done": true },
+        ],
+    });
+
+    assert_eq!(a.to_value(), expected);
+    assert_eq!(b.to_value(), expected);
+
+    let title = a.get(&["todos".into(), 1.into(), "title".into()]).unwrap();
+    assert_eq!(title.to_value(), "milk (oat)");
+
+    // removing an item, and a field, is replicated as any other edit
+    let ops = [
+        a.remove(&["todos".into(), 2.into()]),
+        a.remove(&["title".into()]),
+    ];
+    for op in ops.iter().flatten() {
+        b.apply(op);
+    }
+
+    assert_eq!(Value::from(&b), Value::from(&a));
+    assert_eq!(b.to_value()["todos"].as_array().map(Vec::len), Some(2));
+    assert!(b.get(&["title".into()]).is_none());
+}
+
+#[test]
+fn rebuilt_texts_keep_their_clock() {
+    use serde_json::json;
+
+    // a text is rebuilt at each replica from the operations that made it, its clock along with them
+
+    let mut a = Document::with_uuid(1);
+    let mut b = Document::with_uuid(2);
+
+    for op in a.set(&["t".into()], &json!("hi")) {
+        b.apply(&op);
+    }
+
+    // b has seen every character of the text, but not the edit that comes after them
+    let Some(Node::Text(text)) = b.get(&["t".into()]) else {
+        panic!("not a text");
+    };
+    let version = text.version().clone();
+
+    let ops = a.edit_text(&["t".into()], |text| text.insert_str_at(2, "!"));
+    let Some(Node::Text(text)) = a.get(&["t".into()]) else {
+        panic!("not a text");
+    };
+    assert_eq!(text.operations_since(&version).len(), ops.len());
+
+    for op in &ops {
+        b.apply(op);
+    }
+    assert_eq!(b.to_value(), json!({ "t": "hi!" }));
+}
+
+#[test]
+fn colliding_sites() {
+    use serde_json::json;
+
+    // the UUIDs of both would claim the same site on their own
+    let mut a = Document::with_uuid(1);
+    let mut b = Document::with_uuid(1 + u16::MAX as u128);
+
+    a.assign(&json!({ "todos": ["milk"], "title": "Groceries" }));
+    b.merge(&a);
+
+    // concurrently: a adds a todo, while b retitles the list and edits the first todo
+    a.insert(&["todos".into()], 1, &json!("eggs"));
+    b.edit_text(&["title".into()], |text| text.insert_str_at(0, "My "));
+    b.edit_text(&["todos".into(), 0.into()], |text| {
+        text.insert_str_at(4, "!")
+    });
+
+    b.merge(&a);
+    a.merge(&b);
+
+    let expected = json!({ "todos": ["milk!", "eggs"], "title": "My Groceries" });
+    assert_eq!(b.to_value(), expected);
+    assert_eq!(a.to_value(), b.to_value());
+
+    // as they do by applying each other’s operations, which carry the UUIDs of their sites
+    let mut a = Document::with_uuid(1);
+    let mut b = Document::with_uuid(1 + u16::MAX as u128);
+    for op in a.assign(&json!({ "todos": ["milk"] })) {
+        b.apply(&op);
+    }
+
+    let added = b.insert(&["todos".into()], 1, &json!("eggs")).unwrap();
+    let edited = a.edit_text(&["todos".into(), 0.into()], |text| {
+        text.insert_str_at(4, "!")
+    });
+    assert!(a.apply(&added));
+    for op in &edited {
+        assert!(b.apply(op));
+    }
+
+    let expected = json!({ "todos": ["milk!", "eggs"] });
+    assert_eq!(a.to_value(), expected);
+    assert_eq!(b.to_value(), expected);
+}
+
+#[test]
+fn from_values() {
+    use serde_json::json;
+
+    let value = json!({
+        "title": "Groceries",
+        "todos": [{ "title": "milk", "done": false, "count": 2, "price": 1.5 }, null],
+        "id": u64::MAX, // too large for an `i64`, yet not to be rounded
+    });
+
+    let document = Document::from_value(&value).unwrap();
+    assert_eq!(document.to_value(), value);
+
+    let document = Document::try_from(value.clone()).ok().unwrap();
+    assert_eq!(Value::from(&document), value);
+
+    // the root of a document is always an object
+    assert_eq!(Document::try_from(json!([1, 2])).err(), Some(json!([1, 2])));
+    assert!(Document::from_value(&json!("text")).is_none());
+}
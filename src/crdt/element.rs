@@ -0,0 +1,90 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+/// A value that can be held in a [`Storage`](crate::Storage) sequence.
+///
+/// The provided methods suit values that aren’t text: each one counts as a single
+/// grapheme, and code unit, and none of them end a line. So, for most types, an
+/// empty `impl Element for Row {}` is all that’s needed.
+pub trait Element: Clone {
+    /// How many of the preceding elements [`Element::begins_grapheme()`] is given.
+    const CONTEXT: usize = 0;
+
+    /// Whether the element ends a line.
+    fn is_newline(&self) -> bool {
+        false
+    }
+
+    /// Whether a grapheme cluster begins at this element, when it follows those in `context`.
+    fn begins_grapheme(&self, context: &[Self]) -> bool {
+        let _ = context;
+        true
+    }
+
+    /// The length of the element in UTF-8 bytes.
+    fn len_utf8(&self) -> usize {
+        1
+    }
+
+    /// The length of the element in UTF-16 code units.
+    fn len_utf16(&self) -> usize {
+        1
+    }
+}
+
+impl Element for char {
+    const CONTEXT: usize = 16;
+
+    fn is_newline(&self) -> bool {
+        *self == '\n'
+    }
+
+    fn begins_grapheme(&self, context: &[Self]) -> bool {
+        let mut string = String::from_iter(context);
+        let offset = string.len();
+        string.push(*self);
+
+        string.grapheme_indices(true).any(|(n, _)| n == offset)
+    }
+
+    fn len_utf8(&self) -> usize {
+        char::len_utf8(*self)
+    }
+
+    fn len_utf16(&self) -> usize {
+        char::len_utf16(*self)
+    }
+}
+
+macro_rules! elements {
+    ($($ty:ty),*) => { $(impl Element for $ty {})* };
+}
+
+elements!(bool, u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, String);
+
+#[test]
+fn replicated_list() {
+    use crate::{Merge, Storage};
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Todo(&'static str, bool);
+    impl Element for Todo {}
+
+    let mut a = Storage::with_uuid(1);
+    let mut b = Storage::with_uuid(2);
+
+    for op in a.append([Todo("milk", false), Todo("eggs", false)]) {
+        b.apply(&op);
+    }
+
+    let _ = a.insert_at(1, Todo("bread", false));
+    let _ = b.remove_at(0);
+    b.merge(&a);
+    a.merge(&b);
+
+    let todos =
+        |storage: &Storage<Todo>| storage.elements(..).map(|(_, t)| t.0).collect::<Vec<_>>();
+    assert_eq!(todos(&a), ["bread", "eggs"]);
+    assert_eq!(todos(&a), todos(&b));
+    assert_eq!(a.len(), 2);
+    assert_eq!(a.get(a.position_at(1).unwrap()), Some(&Todo("eggs", false)));
+}
@@ -0,0 +1,78 @@
+use std::fmt::{Display, Formatter};
+
+use crate::UnknownSite;
+
+/// Why an edit couldn’t be made to a [`Storage`](crate::Storage).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// There is no element at the `Position`; nor has there ever been, as far as this replica knows.
+    UnknownPosition,
+    /// The element at the `Position` has been removed. Positions are never reused.
+    Deleted,
+    /// There is already an element at the `Position`; values are never replaced.
+    Duplicate,
+    /// The `Position::first()` and `Position::last()` sentinels can’t be inserted at, or removed.
+    Sentinel,
+    /// A new path between the neighbouring positions would be longer than a `Position` can hold.
+    Exhausted,
+    /// An `Operation` mentions a site without the UUID it belongs to; so which it is can’t be told.
+    UnknownSite,
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Error::UnknownPosition => "no element at that position",
+            Error::Deleted => "the element at that position has been removed",
+            Error::Duplicate => "an element is already at that position",
+            Error::Sentinel => "the first and last positions can't be edited",
+            Error::Exhausted => "no room for a position between its neighbours",
+            Error::UnknownSite => "the operation doesn't say which replica made it",
+        })
+    }
+}
+
+impl From<UnknownSite> for Error {
+    fn from(_: UnknownSite) -> Self {
+        Error::UnknownSite
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[test]
+fn failure_modes() {
+    use crate::{Operation, Position, Storage};
+
+    let mut a = Storage::with_uuid(1);
+    let mut b = Storage::with_uuid(2);
+
+    let ops = a.append("ab".chars());
+    let (x, y) = (ops[0].position().clone(), ops[1].position().clone());
+
+    assert_eq!(b.try_insert('c', &x), Err(Error::UnknownPosition));
+    assert_eq!(b.try_remove(&x), Err(Error::UnknownPosition));
+    assert_eq!(b.try_apply(&ops[1]), Ok(()));
+    assert_eq!(b.try_apply(&ops[1]), Err(Error::Duplicate));
+
+    let op = a.try_remove(&y).unwrap();
+    assert_eq!(a.try_remove(&y), Err(Error::Deleted));
+    assert_eq!(a.try_insert('c', &y), Err(Error::Deleted));
+    assert_eq!(b.try_apply(&op), Ok(()));
+    assert_eq!(b.try_apply(&ops[1]), Err(Error::Deleted));
+
+    // the delete of an insert yet to arrive is remembered, all the same
+    let op = a.try_remove(&x).unwrap();
+    assert_eq!(b.try_apply(&op), Err(Error::UnknownPosition));
+    assert_eq!(b.try_apply(&ops[0]), Err(Error::Deleted));
+
+    assert_eq!(a.try_insert('c', &Position::first()), Err(Error::Sentinel));
+    assert_eq!(a.try_remove(&Position::last()), Err(Error::Sentinel));
+    assert_eq!(
+        a.try_apply(&Operation::insert(Position::first(), 'c', a.sites())),
+        Err(Error::Sentinel)
+    );
+
+    assert!(a.try_insert('c', &Position::last()).is_ok());
+    assert_eq!(a.string(..), "c");
+}
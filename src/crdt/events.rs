@@ -0,0 +1,147 @@
+use std::collections::BTreeSet;
+use std::ops::Range;
+use std::sync::mpsc::{channel, Receiver};
+
+use crate::{Element, Operation, Position, Storage};
+
+/// What an edit changed, sent to every subscriber of a [`Storage`].
+///
+/// A single event is sent for each call that edits the document — an [`Storage::append()`] of a
+/// whole run, say, or a [`Storage::transaction()`] — and for each remote operation or merge.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChangeEvent {
+    /// The positions of the elements inserted, in order.
+    pub inserted: Vec<Position>,
+    /// The positions of the elements removed, in order.
+    pub removed: Vec<Position>,
+    /// The (zero-based) lines, of the document as it now is, whose content changed. When newlines
+    /// were inserted or removed, the lines after these have moved as well.
+    pub lines: Range<usize>,
+    pub origin: Origin,
+}
+
+/// Where the edit behind a [`ChangeEvent`] was made.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Origin {
+    /// By this replica.
+    Local,
+    /// By another, and integrated with [`Storage::apply()`] or [`Merge`](crate::Merge).
+    Remote,
+}
+
+impl<T: Element> Storage<T> {
+    /// Returns a channel on which a [`ChangeEvent`] is sent for every edit from now on.
+    ///
+    /// Dropping the `Receiver` unsubscribes.
+    pub fn subscribe(&mut self) -> Receiver<ChangeEvent> {
+        let (sender, receiver) = channel();
+        self.observers.push(sender);
+        receiver
+    }
+
+    /// Notifies the subscribers of the changes made by `ops`; unless they’re part of a transaction,
+    /// which does so once it’s complete.
+    pub(crate) fn notify(&mut self, ops: &[Operation<T>], origin: Origin) {
+        if ops.is_empty() || self.observers.is_empty() || self.transaction.is_some() {
+            return;
+        }
+
+        let fresh: BTreeSet<_> = ops
+            .iter()
+            .filter(|op| matches!(op, Operation::Insert { .. }))
+            .map(Operation::position)
+            .collect();
+
+        // one that was both inserted and removed within the same transaction is left out
+        let (mut inserted, mut removed) = (Vec::new(), Vec::new());
+        for op in ops {
+            match op {
+                Operation::Insert { pos, .. } if self.elements.contains_key(pos) => {
+                    inserted.push(pos.clone())
+                }
+                Operation::Delete { pos, .. } if !fresh.contains(pos) => removed.push(pos.clone()),
+                _ => {}
+            }
+        }
+
+        inserted.sort();
+        removed.sort();
+
+        // a removed element leaves its line changed, as does an inserted one — and the line it
+        // starts, if it is a newline
+        let lines = Iterator::chain(
+            inserted.iter().map(|pos| {
+                let line = self.index.rank(pos).newlines;
+                line..line + 1 + self.elements[pos].is_newline() as usize
+            }),
+            removed.iter().map(|pos| {
+                let line = self.index.rank(pos).newlines;
+                line..line + 1
+            }),
+        )
+        .reduce(|a, b| Ord::min(a.start, b.start)..Ord::max(a.end, b.end))
+        .unwrap_or_default();
+
+        let event = ChangeEvent {
+            inserted,
+            removed,
+            lines,
+            origin,
+        };
+
+        self.observers
+            .retain(|observer| observer.send(event.clone()).is_ok());
+    }
+}
+
+#[test]
+fn local_and_remote_changes() {
+    use crate::Merge;
+
+    let mut a = Storage::with_uuid(1);
+    let mut b = Storage::with_uuid(2);
+    let events = b.subscribe();
+
+    let ops = a.append("one\ntwo\nthree".chars());
+    for op in &ops {
+        b.apply(op);
+    }
+
+    let ops = b.remove_range(5..9);
+    assert_eq!(b.string(..), "one\nthree");
+    a.merge(&b);
+
+    let events: Vec<_> = events.try_iter().collect();
+    assert_eq!(events.len(), 13 + 1);
+    assert!(events[..13].iter().all(|e| e.origin == Origin::Remote));
+
+    // the newline ending “two” is removed, joining it to “three”
+    let last = events.last().unwrap();
+    assert_eq!(last.origin, Origin::Local);
+    assert_eq!(last.removed.len(), 4);
+    assert_eq!(last.lines, 1..2);
+    assert!(ops.iter().map(Operation::position).eq(&last.removed));
+
+    // as the merge changed nothing, nor was there an event
+    let events = a.subscribe();
+    a.merge(&b);
+    a.transaction(|tx| tx.insert_str_at(0, "zero\n"));
+
+    let events: Vec<_> = events.try_iter().collect();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].inserted.len(), 5);
+    assert_eq!(events[0].lines, 0..2);
+
+    // nor does one inserted and removed by it count, either way
+    let events = a.subscribe();
+    a.transaction(|tx| {
+        tx.insert_at(0, 'a');
+        tx.remove_at(0);
+        tx.insert_at(0, 'b');
+    });
+
+    let events: Vec<_> = events.try_iter().collect();
+    assert_eq!(events.len(), 1);
+    assert_eq!((events[0].inserted.len(), events[0].removed.len()), (1, 0));
+    assert_eq!(events[0].lines, 0..1);
+}
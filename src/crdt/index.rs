@@ -0,0 +1,338 @@
+//! An order-statistic tree over the positions in a document.
+//!
+//! `characters` is a `BTreeMap`, which can’t tell how many keys come before a given one.
+//! This [treap](https://en.wikipedia.org/wiki/Treap) sits beside it with each node caching
+//! a [`Summary`] of its subtree, so translating between offsets and positions — in characters,
+//! graphemes, lines or code units — is logarithmic rather than a walk from the start of the document.
+
+use std::cmp::Ordering;
+use std::ops::{Add, Sub};
+
+use crate::Position;
+
+/// The weight of a character, or the totals over a range of them.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Summary {
+    pub chars: usize,
+    pub graphemes: usize, // characters that begin a grapheme cluster
+    pub newlines: usize,
+    pub utf8: usize,
+    pub utf16: usize,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub(crate) enum Metric {
+    Chars,
+    Graphemes,
+    Newlines,
+    Utf8,
+    Utf16,
+}
+
+impl Summary {
+    pub fn get(&self, metric: Metric) -> usize {
+        match metric {
+            Metric::Chars => self.chars,
+            Metric::Graphemes => self.graphemes,
+            Metric::Newlines => self.newlines,
+            Metric::Utf8 => self.utf8,
+            Metric::Utf16 => self.utf16,
+        }
+    }
+}
+
+impl Add for Summary {
+    type Output = Summary;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Summary {
+            chars: self.chars + rhs.chars,
+            graphemes: self.graphemes + rhs.graphemes,
+            newlines: self.newlines + rhs.newlines,
+            utf8: self.utf8 + rhs.utf8,
+            utf16: self.utf16 + rhs.utf16,
+        }
+    }
+}
+
+impl Sub for Summary {
+    type Output = Summary;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        Summary {
+            chars: self.chars - rhs.chars,
+            graphemes: self.graphemes - rhs.graphemes,
+            newlines: self.newlines - rhs.newlines,
+            utf8: self.utf8 - rhs.utf8,
+            utf16: self.utf16 - rhs.utf16,
+        }
+    }
+}
+
+type Link = Option<u32>;
+
+struct Node {
+    pos: Position,
+    summary: Summary,
+    total: Summary, // of the whole subtree, including this node
+    priority: u32,
+    left: Link,
+    right: Link,
+}
+
+#[derive(Default)]
+pub(crate) struct Index {
+    nodes: Vec<Option<Node>>,
+    free: Vec<u32>,
+    root: Link,
+    rng: fastrand::Rng,
+}
+
+impl Index {
+    /// Returns the totals over the whole document.
+    pub fn total(&self) -> Summary {
+        self.subtotal(self.root)
+    }
+
+    pub fn insert(&mut self, pos: Position, summary: Summary) {
+        let (left, right) = self.split(self.root, &pos);
+
+        let node = Node {
+            pos,
+            summary,
+            total: summary,
+            priority: self.rng.u32(..),
+            left: None,
+            right: None,
+        };
+
+        let i = match self.free.pop() {
+            Some(i) => {
+                self.nodes[i as usize] = Some(node);
+                i
+            }
+            None => {
+                self.nodes.push(Some(node));
+                self.nodes.len() as u32 - 1
+            }
+        };
+
+        let left = self.join(left, Some(i));
+        self.root = self.join(left, right);
+    }
+
+    pub fn remove(&mut self, pos: &Position) -> Option<Summary> {
+        let (root, removed) = self.delete(self.root, pos);
+        self.root = root;
+        removed
+    }
+
+    pub fn get(&self, pos: &Position) -> Option<Summary> {
+        let mut link = self.root;
+        while let Some(i) = link {
+            let node = self.node(i);
+            link = match pos.cmp(&node.pos) {
+                Ordering::Less => node.left,
+                Ordering::Greater => node.right,
+                Ordering::Equal => return Some(node.summary),
+            };
+        }
+
+        None
+    }
+
+    /// Replaces the [`Summary`] of the (existing) `pos`.
+    pub fn update(&mut self, pos: &Position, summary: Summary) {
+        self.replace(self.root, pos, summary);
+    }
+
+    /// Returns the totals of every position before `pos` (which need not be in the index).
+    pub fn rank(&self, pos: &Position) -> Summary {
+        let mut summary = Summary::default();
+
+        let mut link = self.root;
+        while let Some(i) = link {
+            let node = self.node(i);
+            if node.pos < *pos {
+                summary = summary + self.subtotal(node.left) + node.summary;
+                link = node.right;
+            } else {
+                link = node.left;
+            }
+        }
+
+        summary
+    }
+
+    /// Returns the `n`th (zero-based) position that counts towards the `metric`.
+    pub fn select(&self, metric: Metric, mut n: usize) -> Option<&Position> {
+        let mut link = self.root;
+        while let Some(i) = link {
+            let node = self.node(i);
+            let left = self.subtotal(node.left).get(metric);
+            if n < left {
+                link = node.left;
+                continue;
+            }
+
+            n -= left;
+            match node.summary.get(metric) {
+                weight if n < weight => return Some(&node.pos),
+                weight => n -= weight,
+            }
+
+            link = node.right;
+        }
+
+        None
+    }
+
+    fn node(&self, i: u32) -> &Node {
+        // SAFETY: links only ever refer to occupied slots
+        self.nodes[i as usize].as_ref().unwrap()
+    }
+
+    fn node_mut(&mut self, i: u32) -> &mut Node {
+        // SAFETY: links only ever refer to occupied slots
+        self.nodes[i as usize].as_mut().unwrap()
+    }
+
+    fn subtotal(&self, link: Link) -> Summary {
+        link.map(|i| self.node(i).total).unwrap_or_default()
+    }
+
+    fn recount(&mut self, i: u32) {
+        let node = self.node(i);
+        let total = self.subtotal(node.left) + node.summary + self.subtotal(node.right);
+        self.node_mut(i).total = total;
+    }
+
+    /// Splits the subtree into the positions before `pos`, and those at or after it.
+    fn split(&mut self, link: Link, pos: &Position) -> (Link, Link) {
+        let Some(i) = link else {
+            return (None, None);
+        };
+
+        if self.node(i).pos < *pos {
+            let (left, right) = self.split(self.node(i).right, pos);
+            self.node_mut(i).right = left;
+            self.recount(i);
+            (Some(i), right)
+        } else {
+            let (left, right) = self.split(self.node(i).left, pos);
+            self.node_mut(i).left = right;
+            self.recount(i);
+            (left, Some(i))
+        }
+    }
+
+    /// Joins two subtrees, where every position in `left` is before those in `right`.
+    fn join(&mut self, left: Link, right: Link) -> Link {
+        match (left, right) {
+            (None, link) | (link, None) => link,
+            (Some(i), Some(j)) => {
+                if self.node(i).priority > self.node(j).priority {
+                    let link = self.join(self.node(i).right, right);
+                    self.node_mut(i).right = link;
+                    self.recount(i);
+                    Some(i)
+                } else {
+                    let link = self.join(left, self.node(j).left);
+                    self.node_mut(j).left = link;
+                    self.recount(j);
+                    Some(j)
+                }
+            }
+        }
+    }
+
+    fn delete(&mut self, link: Link, pos: &Position) -> (Link, Option<Summary>) {
+        let Some(i) = link else {
+            return (None, None);
+        };
+
+        let removed = match pos.cmp(&self.node(i).pos) {
+            Ordering::Less => {
+                let (link, removed) = self.delete(self.node(i).left, pos);
+                self.node_mut(i).left = link;
+                removed
+            }
+            Ordering::Greater => {
+                let (link, removed) = self.delete(self.node(i).right, pos);
+                self.node_mut(i).right = link;
+                removed
+            }
+            Ordering::Equal => {
+                let node = self.nodes[i as usize].take().unwrap();
+                self.free.push(i);
+                return (self.join(node.left, node.right), Some(node.summary));
+            }
+        };
+
+        self.recount(i);
+        (Some(i), removed)
+    }
+
+    fn replace(&mut self, link: Link, pos: &Position, summary: Summary) {
+        let Some(i) = link else {
+            return;
+        };
+
+        match pos.cmp(&self.node(i).pos) {
+            Ordering::Less => self.replace(self.node(i).left, pos, summary),
+            Ordering::Greater => self.replace(self.node(i).right, pos, summary),
+            Ordering::Equal => self.node_mut(i).summary = summary,
+        }
+
+        self.recount(i);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use quickcheck_macros::quickcheck;
+
+    use super::*;
+
+    #[quickcheck]
+    fn matches_a_sorted_map(edits: Vec<(u16, bool)>) {
+        let mut index = Index::default();
+        let mut model = BTreeMap::new();
+
+        for (key, newline) in edits {
+            let pos = Position::new(1, 0, &[key as u32 + 2]);
+            let summary = Summary {
+                chars: 1,
+                graphemes: 1,
+                newlines: newline as usize,
+                utf8: 1,
+                utf16: 1,
+            };
+
+            match model.remove(&pos) {
+                Some(old) => assert_eq!(index.remove(&pos), Some(old)),
+                None => {
+                    index.insert(pos.clone(), summary);
+                    model.insert(pos, summary);
+                }
+            }
+        }
+
+        assert_eq!(index.total().chars, model.len());
+        for (n, (pos, summary)) in model.iter().enumerate() {
+            assert_eq!(index.get(pos), Some(*summary));
+            assert_eq!(index.rank(pos).chars, n);
+            assert_eq!(index.select(Metric::Chars, n), Some(pos));
+        }
+
+        let newlines: Vec<_> = model.iter().filter(|(_, s)| s.newlines > 0).collect();
+        for (n, (pos, _)) in newlines.iter().enumerate() {
+            assert_eq!(index.select(Metric::Newlines, n), Some(*pos));
+            assert_eq!(index.rank(pos).newlines, n);
+        }
+
+        assert_eq!(index.select(Metric::Chars, model.len()), None);
+    }
+}
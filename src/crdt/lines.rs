@@ -0,0 +1,149 @@
+use crate::crdt::index::Metric;
+use crate::{Position, Storage};
+
+/// What a column is measured in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Unit {
+    /// Unicode scalar values, as yielded by [`str::chars()`].
+    Chars,
+    /// Extended grapheme clusters, i.e. user-perceived characters.
+    Graphemes,
+    /// Bytes of UTF-8, as used by Rust strings.
+    Utf8,
+    /// UTF-16 code units, as used by JavaScript, Java and the Language Server Protocol.
+    Utf16,
+}
+
+impl From<Unit> for Metric {
+    fn from(unit: Unit) -> Self {
+        match unit {
+            Unit::Chars => Metric::Chars,
+            Unit::Graphemes => Metric::Graphemes,
+            Unit::Utf8 => Metric::Utf8,
+            Unit::Utf16 => Metric::Utf16,
+        }
+    }
+}
+
+impl Storage {
+    /// Returns the number of lines in the document; which is always at least one.
+    pub fn line_count(&self) -> usize {
+        self.newlines.len() - 1 // `Position::first()` and `Position::last()` bracket the lines
+    }
+
+    /// Returns the (zero-based) line and column of the character at `pos`, or of the end
+    /// of the document for [`Position::last()`].
+    ///
+    /// A character in the middle of a grapheme cluster is at the column of its cluster.
+    pub fn line_col_of(&self, pos: &Position, unit: Unit) -> Option<(usize, usize)> {
+        let weight = match self.index.get(pos) {
+            Some(summary) => summary,
+            None if *pos == Position::last() => Default::default(),
+            None => return None,
+        };
+
+        let line = self.line_of(pos);
+        let metric = Metric::from(unit);
+
+        let mut col = self.index.rank(pos).get(metric) - self.line_offset(line, metric)?;
+        if unit == Unit::Graphemes && weight.chars == 1 && weight.graphemes == 0 {
+            col = col.saturating_sub(1); // within the preceding cluster
+        }
+
+        Some((line, col))
+    }
+
+    /// Returns the [`Position`] of the character at the (zero-based) `line` and `col`.
+    ///
+    /// A `col` at the end of the line returns the newline that ends it, or [`Position::last()`]
+    /// on the last line, so the result can always be passed to [`Storage::insert()`]. Returns
+    /// `None` if the `line` or `col` are out of range, or if `col` falls within a character.
+    pub fn position_at_line_col(&self, line: usize, col: usize, unit: Unit) -> Option<&Position> {
+        let metric = Metric::from(unit);
+        let offset = self.line_offset(line, metric)? + col;
+
+        // the newline ending this `line`
+        let end = match self.line_start(line + 1) {
+            Some(pos) => pos,
+            None => self.last(),
+        };
+
+        match self.index.rank(end).get(metric) {
+            len if offset > len => None,
+            len if offset == len => Some(end),
+            _ => self
+                .index
+                .select(metric, offset)
+                .filter(|pos| self.index.rank(pos).get(metric) == offset),
+        }
+    }
+
+    /// Returns the offset, in `metric`, at which the (zero-based) `line` starts.
+    fn line_offset(&self, line: usize, metric: Metric) -> Option<usize> {
+        match line {
+            0 => Some(0),
+            _ => {
+                let newline = self.index.select(Metric::Newlines, line - 1)?;
+                let weight = self.index.get(newline)?;
+                Some(self.index.rank(newline).get(metric) + weight.get(metric))
+            }
+        }
+    }
+}
+
+#[test]
+fn lines_and_columns() {
+    let string = "one\ntwö\n\n👧🏻 e\u{301}!";
+    let storage = Storage::from(string);
+
+    assert_eq!(storage.line_count(), 4);
+    assert_eq!(Storage::default().line_count(), 1);
+
+    // every character round-trips, in every unit
+    let units = [Unit::Chars, Unit::Utf8, Unit::Utf16];
+    for unit in units {
+        for (pos, _) in storage.characters(..) {
+            let (line, col) = storage.line_col_of(pos, unit).unwrap();
+            assert_eq!(storage.position_at_line_col(line, col, unit), Some(pos));
+        }
+    }
+
+    let (last, _) = string.lines().enumerate().last().unwrap();
+    let line = string.lines().last().unwrap();
+    let end = |unit| storage.line_col_of(&Position::last(), unit);
+    assert_eq!(end(Unit::Chars), Some((last, line.chars().count())));
+    assert_eq!(end(Unit::Graphemes), Some((last, 4)));
+    assert_eq!(end(Unit::Utf8), Some((last, line.len())));
+    assert_eq!(end(Unit::Utf16), Some((last, line.encode_utf16().count())));
+
+    // columns within a line
+    let ö = storage.position_at(6).unwrap();
+    assert_eq!(storage.line_col_of(ö, Unit::Chars), Some((1, 2)));
+    assert_eq!(storage.position_at_line_col(1, 3, Unit::Utf8), None); // within `ö`
+    assert_eq!(
+        storage.position_at_line_col(1, 4, Unit::Utf8),
+        storage.position_at(7)
+    );
+    assert_eq!(storage.position_at_line_col(1, 4, Unit::Chars), None); // past the end
+    assert_eq!(
+        storage.position_at_line_col(2, 0, Unit::Chars),
+        storage.position_at(8)
+    );
+    assert_eq!(storage.position_at_line_col(4, 0, Unit::Chars), None);
+
+    // graphemes: `👧🏻` is one cluster, as is `e\u{301}`
+    let modifier = storage.position_at(10).unwrap();
+    let accent = storage.position_at(13).unwrap();
+    assert_eq!(storage.line_col_of(modifier, Unit::Graphemes), Some((3, 0)));
+    assert_eq!(storage.line_col_of(accent, Unit::Graphemes), Some((3, 2)));
+    assert_eq!(
+        storage.position_at_line_col(3, 3, Unit::Graphemes),
+        storage.position_at(14)
+    );
+    assert_eq!(
+        storage.position_at_line_col(3, 4, Unit::Graphemes),
+        Some(&Position::last())
+    );
+
+    assert_eq!(storage.line_col_of(&Position::first(), Unit::Chars), None);
+}
@@ -0,0 +1,545 @@
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{Element, Merge, Operation, Position, SiteRegistry, Storage};
+
+/// A replicated map, where the latest assignment to each key wins.
+///
+/// Each assignment is appended, as an [`Assignment`], to a [`Storage`] of its own; so a map shares
+/// the site ids, clock and [`Operation`]s of a text, and converges as one does — renumbering its
+/// sites, should two collide, included. Of the assignments to a key, the one at the latest
+/// [`Position`] wins: an assignment is appended after every other its site has seen, so wins over
+/// them all, while concurrent ones are settled by the order of their positions. The assignment it
+/// replaces is removed; and, as removals are remembered, isn’t brought back should it arrive late.
+///
+/// A value can also be a text, edited in place — and merged — rather than replaced.
+pub struct Map<K, V> {
+    fields: Fields<K, Assigned<V>, Value<V>>,
+}
+
+/// A value of a [`Map`].
+pub enum Value<V> {
+    Scalar(V),
+    Text(Box<Storage>),
+}
+
+/// The assignment of a value — or, without one, the removal — of a key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Assignment<K, I> {
+    pub key: K,
+    pub value: Option<I>,
+}
+
+/// The value assigned to a key of a [`Map`]: a scalar, or an empty text.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Assigned<V> {
+    Scalar(V),
+    Text,
+}
+
+/// An edit, made at one site, of a [`Map`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MapOperation<K, V> {
+    /// Inserts, or removes, an assignment.
+    Assign(Operation<Assignment<K, Assigned<V>>>),
+    /// Edits the text at the key.
+    Edit { key: K, op: Operation },
+}
+
+/// A value that is built from the description its assignment carries, and merged with the value
+/// built from the same assignment at another replica.
+pub(crate) trait Assign: Sized {
+    type Init: Clone;
+
+    /// Builds the value described by `init`, for the replica identified by `uuid`; its texts
+    /// registering the `sites` known to the replica, so that theirs agree.
+    fn build(init: &Self::Init, sites: &SiteRegistry, uuid: u128) -> Self;
+
+    fn merge(&mut self, other: &Self);
+}
+
+/// The assignments to each key, described by an `I`, along with the value of the latest of them.
+///
+/// Shared by a [`Map`] and the objects of a `Document`.
+pub(crate) struct Fields<K, I, N> {
+    assignments: Storage<Assignment<K, I>>,
+    latest: BTreeMap<K, (Position, Option<N>)>, // `None` once removed
+    keys: BTreeMap<Position, K>,                // of each assignment yet to be removed…
+    standing: BTreeSet<(K, Position)>,          // …and the same, by key
+    len: usize,                                 // the number of keys with a value
+}
+
+impl<K: Clone, I: Clone> Element for Assignment<K, I> {}
+
+impl<K: Ord + Clone, V: Clone> Default for Map<K, V> {
+    fn default() -> Self {
+        Self::with_uuid(crate::crdt::sites::uuid())
+    }
+}
+
+impl<K: Ord + Clone, V: Clone> Map<K, V> {
+    /// Creates a replica for the site identified by `uuid`.
+    pub fn with_uuid(uuid: u128) -> Self {
+        Map {
+            fields: Fields::new(&SiteRegistry::default(), uuid),
+        }
+    }
+
+    /// Returns the registry of every site known to have assigned a key.
+    pub fn sites(&self) -> &SiteRegistry {
+        self.fields.sites()
+    }
+
+    pub fn get(&self, key: &K) -> Option<&Value<V>> {
+        self.fields.get(key)
+    }
+
+    /// Iterates over the keys, and their values, in order.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &Value<V>)> {
+        self.fields.iter()
+    }
+
+    /// Returns the number of keys.
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Assigns `value` to `key`, returning the [`MapOperation`]s needed to replicate it.
+    pub fn set(&mut self, key: K, value: V) -> Vec<MapOperation<K, V>> {
+        Self::assigned(self.fields.assign(key, Some(Assigned::Scalar(value))))
+    }
+
+    /// Assigns an empty text to `key`, returning the [`MapOperation`]s needed to replicate it.
+    pub fn set_text(&mut self, key: K) -> Vec<MapOperation<K, V>> {
+        Self::assigned(self.fields.assign(key, Some(Assigned::Text)))
+    }
+
+    /// Removes `key`, returning the [`MapOperation`]s needed to replicate it.
+    ///
+    /// Returns no operations if there is no such key.
+    pub fn remove(&mut self, key: &K) -> Vec<MapOperation<K, V>> {
+        if self.get(key).is_none() {
+            return Vec::new();
+        }
+
+        Self::assigned(self.fields.assign(key.clone(), None))
+    }
+
+    /// Edits the text at `key` with `f`, returning the [`MapOperation`]s needed to replicate it.
+    ///
+    /// Returns no operations if there is no text at `key`.
+    pub fn edit(
+        &mut self,
+        key: &K,
+        f: impl FnOnce(&mut Storage) -> Vec<Operation>,
+    ) -> Vec<MapOperation<K, V>> {
+        let Some(Value::Text(text)) = self.fields.get_mut(key) else {
+            return Vec::new();
+        };
+
+        f(text)
+            .into_iter()
+            .map(|op| MapOperation::Edit {
+                key: key.clone(),
+                op,
+            })
+            .collect()
+    }
+
+    /// Integrates a [`MapOperation`] generated by another replica, returning whether the map changed.
+    ///
+    /// Edits to a text that isn’t there — or has since been replaced — are ignored.
+    pub fn apply(&mut self, op: &MapOperation<K, V>) -> bool {
+        match op {
+            MapOperation::Assign(op) => self.fields.apply(op),
+            MapOperation::Edit { key, op } => match self.fields.get_mut(key) {
+                Some(Value::Text(text)) => text.apply(op),
+                _ => false,
+            },
+        }
+    }
+
+    fn assigned(ops: Vec<Operation<Assignment<K, Assigned<V>>>>) -> Vec<MapOperation<K, V>> {
+        ops.into_iter().map(MapOperation::Assign).collect()
+    }
+}
+
+impl<V: Clone> Assign for Value<V> {
+    type Init = Assigned<V>;
+
+    fn build(init: &Assigned<V>, sites: &SiteRegistry, uuid: u128) -> Self {
+        match init {
+            Assigned::Scalar(value) => Value::Scalar(value.clone()),
+            Assigned::Text => Value::Text(Box::new(Storage::with_sites(sites, uuid))),
+        }
+    }
+
+    fn merge(&mut self, other: &Self) {
+        if let (Value::Text(text), Value::Text(theirs)) = (self, other) {
+            text.merge(theirs);
+        }
+    }
+}
+
+impl<K: Ord + Clone, V: Clone> Merge for Map<K, V> {
+    /// The latest assignment to each key in either; merging both versions of a text
+    /// that was assigned just the once.
+    fn merge(&mut self, other: &Self) {
+        self.fields.merge(&other.fields);
+    }
+}
+
+impl<K: Ord + Clone, N: Assign> Fields<K, N::Init, N> {
+    /// Creates the fields of the replica identified by `uuid`, that knows of the same `sites` as
+    /// another.
+    pub fn new(sites: &SiteRegistry, uuid: u128) -> Self {
+        Fields {
+            assignments: Storage::with_sites(sites, uuid),
+            latest: BTreeMap::new(),
+            keys: BTreeMap::new(),
+            standing: BTreeSet::new(),
+            len: 0,
+        }
+    }
+
+    /// Returns the number of keys with a value.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn get<Q: Ord + ?Sized>(&self, key: &Q) -> Option<&N>
+    where
+        K: Borrow<Q>,
+    {
+        self.latest.get(key)?.1.as_ref()
+    }
+
+    pub fn get_mut<Q: Ord + ?Sized>(&mut self, key: &Q) -> Option<&mut N>
+    where
+        K: Borrow<Q>,
+    {
+        self.latest.get_mut(key)?.1.as_mut()
+    }
+
+    /// Iterates over the keys, and their values, in order.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &N)> {
+        self.latest
+            .iter()
+            .filter_map(|(key, (_, value))| Some((key, value.as_ref()?)))
+    }
+
+    /// Returns the registry of every site known to have assigned a key.
+    pub fn sites(&self) -> &SiteRegistry {
+        self.assignments.sites()
+    }
+
+    /// Assigns the value described by `init` to `key`, removing the assignment it replaces, as a
+    /// single edit; returning the [`Operation`]s needed to replicate it.
+    pub fn assign(
+        &mut self,
+        key: K,
+        init: Option<N::Init>,
+    ) -> Vec<Operation<Assignment<K, N::Init>>> {
+        let replaced = self.latest.get(&key).map(|(pos, _)| pos.clone());
+        let ops = self.assignments.transaction(|tx| {
+            tx.append([Assignment { key, value: init }]);
+            if let Some(pos) = &replaced {
+                tx.remove(pos);
+            }
+        });
+
+        for op in &ops {
+            self.update(op);
+        }
+
+        ops
+    }
+
+    /// Integrates an [`Operation`] generated by another replica, returning whether it changed
+    /// the assignments.
+    pub fn apply(&mut self, op: &Operation<Assignment<K, N::Init>>) -> bool {
+        let Ok((op, renames)) = self.assignments.localize(op) else {
+            return false;
+        };
+
+        // a site it mentions may have moved another, that assigned a key, out of the way
+        if !renames.is_empty() {
+            for (pos, _) in self.latest.values_mut() {
+                *pos = pos.renamed(&renames);
+            }
+
+            let keys = std::mem::take(&mut self.keys);
+            self.keys = keys
+                .into_iter()
+                .map(|(pos, key)| (pos.renamed(&renames), key))
+                .collect();
+            let standing = std::mem::take(&mut self.standing);
+            self.standing = standing
+                .into_iter()
+                .map(|(key, pos)| (key, pos.renamed(&renames)))
+                .collect();
+        }
+
+        let changed = self.assignments.integrate(&op).is_ok();
+        if changed {
+            self.update(&op);
+        }
+
+        changed
+    }
+
+    /// The latest assignment to each key in either; merging both values of those assigned by the
+    /// same edit.
+    pub fn merge(&mut self, other: &Self) {
+        let sites = self.sites().clone();
+        self.assignments.merge(&other.assignments);
+
+        // the sites of either may have been renumbered, their positions along with them
+        let mut values: BTreeMap<_, _> = std::mem::take(&mut self.latest)
+            .into_values()
+            .filter_map(|(pos, value)| Some((self.sites().translate(&pos, &sites), value?)))
+            .collect();
+
+        let mut latest = BTreeMap::new();
+        self.keys.clear();
+        self.standing.clear();
+        for (pos, assignment) in self.assignments.elements(..) {
+            latest.insert(
+                assignment.key.clone(),
+                (pos.clone(), assignment.value.as_ref()),
+            );
+            self.keys.insert(pos.clone(), assignment.key.clone());
+            self.standing.insert((assignment.key.clone(), pos.clone()));
+        }
+
+        for (key, (pos, init)) in latest {
+            let mut value = values.remove(&pos).or_else(|| Some(self.build(init?)));
+
+            let theirs = other.latest.get(&key).and_then(|(theirs, value)| {
+                let theirs = self.sites().translate(theirs, other.sites());
+                value.as_ref().filter(|_| theirs == pos)
+            });
+            if let (Some(value), Some(theirs)) = (&mut value, theirs) {
+                value.merge(theirs);
+            }
+
+            self.latest.insert(key, (pos, value));
+        }
+
+        self.len = self.iter().count();
+    }
+
+    /// Brings the value of the key that `op` assigns, or removes the assignment of, up-to-date.
+    fn update(&mut self, op: &Operation<Assignment<K, N::Init>>) {
+        match op {
+            Operation::Insert { pos, value, .. } => {
+                self.keys.insert(pos.clone(), value.key.clone());
+                self.standing.insert((value.key.clone(), pos.clone()));
+
+                if let Some((latest, _)) = self.latest.get(&value.key) {
+                    if latest > pos {
+                        return; // it was already replaced, concurrently
+                    }
+                }
+
+                let node = value.value.as_ref().map(|init| self.build(init));
+                self.set_latest(value.key.clone(), Some((pos.clone(), node)));
+            }
+            Operation::Delete { pos, .. } => {
+                let Some(key) = self.keys.remove(pos) else {
+                    return;
+                };
+                self.standing.remove(&(key.clone(), pos.clone()));
+
+                if self
+                    .latest
+                    .get(&key)
+                    .is_none_or(|(latest, _)| latest != pos)
+                {
+                    return; // it was already replaced
+                }
+
+                // the assignment that replaced it has yet to arrive; until then, the one before it stands
+                let range = (key.clone(), Position::first())..=(key.clone(), Position::last());
+                let previous = self.standing.range(range).next_back();
+                let previous = previous.and_then(|(_, pos)| {
+                    let assignment = self.assignments.get(pos)?;
+                    let node = assignment.value.as_ref().map(|init| self.build(init));
+                    Some((pos.clone(), node))
+                });
+                self.set_latest(key, previous);
+            }
+        }
+    }
+
+    /// Makes `latest` the latest assignment to `key`, or forgets the key without one; keeping count
+    /// of the keys with a value.
+    fn set_latest(&mut self, key: K, latest: Option<(Position, Option<N>)>) {
+        let before = self.get(&key).is_some();
+        let after = matches!(latest, Some((_, Some(_))));
+
+        match latest {
+            Some(latest) => self.latest.insert(key, latest),
+            None => self.latest.remove(&key),
+        };
+        self.len = self.len + after as usize - before as usize;
+    }
+
+    fn build(&self, init: &N::Init) -> N {
+        N::build(init, self.sites(), self.assignments.uuid())
+    }
+}
+
+#[test]
+fn latest_assignment_wins() {
+    let mut a = Map::with_uuid(1);
+    let mut b = Map::with_uuid(2);
+    let mut c = Map::with_uuid(3);
+
+    let title = |map: &Map<&str, &'static str>| match map.get(&"title") {
+        Some(Value::Scalar(title)) => Some(*title),
+        _ => None,
+    };
+    let apply = |map: &mut Map<_, _>, ops: &[MapOperation<_, _>]| {
+        for op in ops {
+            map.apply(op);
+        }
+    };
+
+    // b renames the title having seen it, so wins whatever order they arrive in
+    let draft = a.set("title", "Draft");
+    apply(&mut b, &draft);
+    let renamed = b.set("title", "Final");
+    apply(&mut c, &renamed);
+    apply(&mut c, &draft);
+    apply(&mut a, &renamed);
+    assert_eq!(title(&a), Some("Final"));
+    assert_eq!(title(&c), Some("Final"));
+
+    // concurrent edits are settled the same way everywhere
+    let removed = a.remove(&"title");
+    let renamed = b.set("title", "Done");
+    apply(&mut a, &renamed);
+    apply(&mut b, &removed);
+    assert_eq!(title(&a), title(&b));
+
+    // and a removal isn’t undone by an assignment it has seen, arriving late
+    let author = b.set("author", "Bo");
+    apply(&mut a, &author);
+    let removed = a.remove(&"author");
+    apply(&mut c, &removed);
+    apply(&mut c, &author);
+    assert!(c.get(&"author").is_none());
+    assert_eq!((a.len(), c.len()), (a.iter().count(), c.iter().count()));
+}
+
+#[test]
+fn replaced_assignments_stand_in() {
+    let mut a = Map::with_uuid(1);
+    let mut b = Map::with_uuid(2);
+    let mut c = Map::with_uuid(3);
+    let mut d = Map::with_uuid(4);
+
+    // two concurrent assignments, one winning over the other
+    let ones = a.set("k", 1);
+    let twos = b.set("k", 2);
+    for op in ones.iter().chain(&twos) {
+        c.apply(op);
+        d.apply(op);
+    }
+    let value = |map: &Map<&str, i32>| match map.get(&"k") {
+        Some(Value::Scalar(value)) => Some(*value),
+        _ => None,
+    };
+    let (won, lost) = match value(&c) {
+        Some(1) => (1, 2),
+        _ => (2, 1),
+    };
+    assert_eq!(value(&d), Some(won));
+
+    // until the removal of the winner arrives whole, the loser stands in for it
+    let removed = d.remove(&"k");
+    assert_eq!(removed.len(), 2);
+    c.apply(&removed[1]);
+    assert_eq!((value(&c), c.len()), (Some(lost), 1));
+    c.apply(&removed[0]);
+    assert_eq!((value(&c), c.len()), (None, 0));
+    assert!(c.is_empty());
+}
+
+#[test]
+fn nested_text() {
+    let mut a = Map::<&str, ()>::with_uuid(1);
+    let mut b = Map::with_uuid(2);
+
+    for op in a.set_text("body") {
+        b.apply(&op);
+    }
+    let ops = a.edit(&"body", |text| text.append("hello".chars()));
+    for op in &ops {
+        b.apply(op);
+    }
+
+    // concurrent edits of the same text are merged, rather than one replacing the other
+    let _ = a.edit(&"body", |text| text.insert_str_at(5, " world"));
+    let _ = b.edit(&"body", |text| text.insert_str_at(0, "oh, "));
+    a.merge(&b);
+    b.merge(&a);
+
+    let string = |map: &Map<&str, ()>| match map.get(&"body") {
+        Some(Value::Text(text)) => text.string(..),
+        _ => String::new(),
+    };
+
+    assert_eq!(string(&a), "oh, hello world");
+    assert_eq!(string(&a), string(&b));
+}
+
+#[test]
+fn colliding_sites() {
+    // two UUIDs given the same site id, until each learns of the other
+    let mut a = Map::<&str, u8>::with_uuid(1);
+    let mut b = Map::with_uuid(1 + u16::MAX as u128);
+    assert_eq!(a.fields.assignments.site, b.fields.assignments.site);
+
+    a.set("x", 1);
+    b.set("x", 2);
+    a.set_text("body");
+    b.set_text("body");
+    let _ = a.edit(&"body", |text| text.append("hello".chars()));
+
+    b.merge(&a);
+    a.merge(&b);
+
+    let value = |map: &Map<&str, u8>| match map.get(&"x") {
+        Some(Value::Scalar(value)) => Some(*value),
+        _ => None,
+    };
+    let string = |map: &Map<&str, u8>| match map.get(&"body") {
+        Some(Value::Text(text)) => text.string(..),
+        _ => String::new(),
+    };
+
+    assert_eq!(value(&a), value(&b));
+    assert_eq!(string(&a), string(&b));
+    assert_eq!(a.sites(), b.sites());
+
+    // as they do by applying each other’s operations, which carry the UUIDs of their sites
+    let mut a = Map::<&str, u8>::with_uuid(1);
+    let mut b = Map::with_uuid(1 + u16::MAX as u128);
+    let ours = a.set("x", 1);
+    let theirs = b.set("x", 2);
+    for op in &ours {
+        assert!(b.apply(op));
+    }
+    for op in &theirs {
+        assert!(a.apply(op));
+    }
+
+    assert_eq!(value(&a), value(&b));
+    assert_eq!(a.sites(), b.sites());
+}
@@ -0,0 +1,442 @@
+//! Formatting — bold, italic, links and the like — over the text of a [`Storage`].
+//!
+//! Following [Peritext](https://www.inkandswitch.com/peritext/), each mark is anchored to the gap
+//! just before or after a character, rather than the character itself, so that whether text typed
+//! at either end of a span takes on its formatting can be chosen per mark: bold grows as you type
+//! after it, a link doesn’t. As removed positions stay ordered with respect to the remaining ones,
+//! anchors survive the removal of their characters.
+//!
+//! A character takes on, for each name, the value of the latest mark covering it; so concurrent
+//! marks, and unmarks, of the same text resolve the same way everywhere. Marks made with the same
+//! clock are told apart by the UUIDs of their replicas, rather than by site ids, which can differ
+//! between replicas yet to learn of the same sites. For the same reason, a mark carries the UUIDs
+//! of the sites its anchors were made by; their positions are translated to the ids those sites
+//! have in a [`Storage`] whenever it is formatted.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Bound::{Excluded, Unbounded};
+use std::ops::Range;
+
+use crate::crdt::index::Metric;
+use crate::{Claims, Element, Merge, Position, Storage};
+
+/// A gap between two elements: just before, or just after, the element at a [`Position`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Anchor {
+    Before(Position),
+    After(Position),
+}
+
+/// Which ends of a span grow to take in the text inserted there.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Expand {
+    Neither,
+    Left,
+    Right,
+    Both,
+}
+
+/// The formatting of — or, without a `value`, its removal from — the text between two anchors.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mark<V> {
+    /// Identifies the mark, along with the `uuid` of the replica that made it; those with a later
+    /// `clock` (and then `uuid`) win.
+    pub clock: u64,
+    pub uuid: u128,
+    pub start: Anchor,
+    pub end: Anchor,
+    /// The UUIDs of the sites of the anchors, by the ids they have at the replica that made it.
+    pub sites: Claims,
+    pub name: String,
+    pub value: Option<V>,
+}
+
+/// A run of text with the same formatting.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Span<V> {
+    pub text: String,
+    pub attributes: BTreeMap<String, V>,
+}
+
+/// The marks of a document, kept beside its [`Storage`].
+///
+/// Marks have a [Lamport clock](https://en.wikipedia.org/wiki/Lamport_timestamp) of their own, so
+/// that they neither use nor skip any of the document’s.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Marks<V> {
+    pub(crate) marks: Vec<Mark<V>>, // in the order they take effect
+    pub(crate) clock: u64,
+}
+
+impl<V> Default for Marks<V> {
+    fn default() -> Self {
+        Marks {
+            marks: Vec::new(),
+            clock: 0,
+        }
+    }
+}
+
+impl Anchor {
+    /// Orders the gaps around an element, and the element itself, by the same `Position`.
+    fn key(&self) -> (&Position, u8) {
+        match self {
+            Anchor::Before(pos) => (pos, 0),
+            Anchor::After(pos) => (pos, 2),
+        }
+    }
+
+    /// Returns the anchor, with the id its site has in `storage`; given the UUIDs of the `sites`
+    /// the replica that made it knew.
+    fn local<T: Element>(&self, sites: &Claims, storage: &Storage<T>) -> Anchor {
+        let local = |pos: &Position| match sites.uuid(pos.site_id()) {
+            Some(uuid) => storage.local(pos, uuid),
+            None => pos.clone(),
+        };
+
+        match self {
+            Anchor::Before(pos) => Anchor::Before(local(pos)),
+            Anchor::After(pos) => Anchor::After(local(pos)),
+        }
+    }
+}
+
+impl<V> Mark<V> {
+    /// Returns the anchors of the span, with the ids their sites have in `storage`.
+    fn anchors<T: Element>(&self, storage: &Storage<T>) -> [Anchor; 2] {
+        [
+            self.start.local(&self.sites, storage),
+            self.end.local(&self.sites, storage),
+        ]
+    }
+
+    fn order(&self) -> (u64, u128) {
+        (self.clock, self.uuid)
+    }
+}
+
+impl<V: Clone + PartialEq> Marks<V> {
+    /// Formats the elements within the `range` of indices, returning the [`Mark`] needed to
+    /// replicate it.
+    ///
+    /// Returns `None` if the `range` is empty or past the end of the document.
+    pub fn add<T: Element>(
+        &mut self,
+        storage: &Storage<T>,
+        range: Range<usize>,
+        name: &str,
+        value: V,
+        expand: Expand,
+    ) -> Option<Mark<V>> {
+        self.mark(storage, range, name, Some(value), expand)
+    }
+
+    /// Removes the formatting from the elements within the `range` of indices, returning the
+    /// [`Mark`] needed to replicate it.
+    ///
+    /// The `expand` given should match that of the marks it removes.
+    pub fn remove<T: Element>(
+        &mut self,
+        storage: &Storage<T>,
+        range: Range<usize>,
+        name: &str,
+        expand: Expand,
+    ) -> Option<Mark<V>> {
+        self.mark(storage, range, name, None, expand)
+    }
+
+    /// Integrates a [`Mark`] made by another replica, returning whether it was new.
+    pub fn apply(&mut self, mark: &Mark<V>) -> bool {
+        self.clock = Ord::max(self.clock, mark.clock);
+
+        match self.marks.binary_search_by_key(&mark.order(), Mark::order) {
+            Ok(_) => false,
+            Err(n) => {
+                self.marks.insert(n, mark.clone());
+                true
+            }
+        }
+    }
+
+    /// Returns the formatting of the element at `pos`, in `storage`.
+    pub fn attributes<T: Element>(
+        &self,
+        storage: &Storage<T>,
+        pos: &Position,
+    ) -> BTreeMap<String, V> {
+        Self::resolve(self.marks.iter().filter(|mark| {
+            let [start, end] = mark.anchors(storage);
+            start.key() < (pos, 1) && (pos, 1) < end.key()
+        }))
+    }
+
+    /// Returns the text of the document, split into runs with the same formatting.
+    ///
+    /// The marks are swept along with the text, so the formatting is only worked out again where
+    /// one of them starts or ends.
+    pub fn spans(&self, storage: &Storage) -> Vec<Span<V>> {
+        let anchors: Vec<_> = self
+            .marks
+            .iter()
+            .map(|mark| mark.anchors(storage))
+            .collect();
+
+        // a mark that ends before it starts covers nothing
+        let marks = (0..self.marks.len()).filter(|n| {
+            let [start, end] = &anchors[*n];
+            start.key() < end.key()
+        });
+
+        let mut starts: Vec<_> = marks.collect();
+        let mut ends = starts.clone();
+        starts.sort_by(|a, b| Ord::cmp(&anchors[*a][0].key(), &anchors[*b][0].key()));
+        ends.sort_by(|a, b| Ord::cmp(&anchors[*a][1].key(), &anchors[*b][1].key()));
+
+        let (mut starts, mut ends) = (starts.into_iter().peekable(), ends.into_iter().peekable());
+        let mut covering = BTreeSet::new(); // in the order the marks take effect
+        let mut attributes = BTreeMap::new();
+        let mut spans: Vec<Span<V>> = Vec::new();
+
+        for (pos, ch) in storage.characters(..) {
+            let mut changed = false;
+            while let Some(n) = starts.next_if(|n| anchors[*n][0].key() < (pos, 1)) {
+                changed |= covering.insert(n);
+            }
+            while let Some(n) = ends.next_if(|n| anchors[*n][1].key() < (pos, 1)) {
+                changed |= covering.remove(&n);
+            }
+
+            if changed {
+                attributes = Self::resolve(covering.iter().map(|n| &self.marks[*n]));
+            }
+
+            match spans.last_mut() {
+                Some(span) if span.attributes == attributes => span.text.push(*ch),
+                _ => spans.push(Span {
+                    text: ch.to_string(),
+                    attributes: attributes.clone(),
+                }),
+            }
+        }
+
+        spans
+    }
+
+    /// Returns the formatting given by the `marks` covering an element, in the order they take effect.
+    fn resolve<'a>(marks: impl DoubleEndedIterator<Item = &'a Mark<V>>) -> BTreeMap<String, V>
+    where
+        V: 'a,
+    {
+        let mut attributes = BTreeMap::new();
+        let mut decided = Vec::new();
+
+        for mark in marks.rev() {
+            if decided.contains(&&mark.name) {
+                continue; // a later mark already decided it
+            }
+
+            decided.push(&mark.name);
+            if let Some(value) = &mark.value {
+                attributes.insert(mark.name.clone(), value.clone());
+            }
+        }
+
+        attributes
+    }
+
+    fn mark<T: Element>(
+        &mut self,
+        storage: &Storage<T>,
+        range: Range<usize>,
+        name: &str,
+        value: Option<V>,
+        expand: Expand,
+    ) -> Option<Mark<V>> {
+        if range.is_empty() {
+            return None;
+        }
+
+        let first = storage.index.select(Metric::Chars, range.start)?;
+        let last = storage.index.select(Metric::Chars, range.end - 1)?;
+
+        let start = match expand {
+            // SAFETY: `Position::first()` is before any element
+            Expand::Left | Expand::Both => Anchor::After(storage.before(first).unwrap().clone()),
+            Expand::Neither | Expand::Right => Anchor::Before(first.clone()),
+        };
+
+        let end = match expand {
+            Expand::Right | Expand::Both => {
+                let after = storage.elements.range((Excluded(last), Unbounded)).next();
+                Anchor::Before(after.map_or(storage.last(), |(pos, _)| pos).clone())
+            }
+            Expand::Neither | Expand::Left => Anchor::After(last.clone()),
+        };
+
+        self.clock += 1;
+        let mark = Mark {
+            clock: self.clock,
+            uuid: storage.uuid,
+            sites: storage
+                .sites
+                .claims([start.key().0.site_id(), end.key().0.site_id()]),
+            start,
+            end,
+            name: name.to_string(),
+            value,
+        };
+
+        self.apply(&mark);
+        Some(mark)
+    }
+}
+
+impl<V: Clone + PartialEq> Merge for Marks<V> {
+    /// The marks of both.
+    fn merge(&mut self, other: &Self) {
+        for mark in &other.marks {
+            self.apply(mark);
+        }
+    }
+}
+
+#[test]
+fn concurrent_formatting() {
+    let mut a = Storage::with_uuid(1);
+    let mut b = Storage::with_uuid(2);
+    for op in a.append("The quick fox".chars()) {
+        b.apply(&op);
+    }
+
+    let (mut x, mut y) = (Marks::default(), Marks::default());
+
+    // “quick fox” is made bold at one replica, while “The quick” is unbolded at the other…
+    let bold = x.add(&a, 4..13, "bold", true, Expand::Right).unwrap();
+    let unbold = y.remove(&b, 0..9, "bold", Expand::Right).unwrap();
+    x.apply(&unbold);
+    y.apply(&bold);
+
+    // …and the later edit wins, as concurrent edits are ordered by their `clock`, then `uuid`
+    let text = |marks: &Marks<bool>, storage: &Storage| {
+        marks
+            .spans(storage)
+            .into_iter()
+            .map(|span| (span.text, span.attributes.contains_key("bold")))
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(x, y);
+    let expected = match unbold.uuid > bold.uuid {
+        // both at the first `clock`
+        true => [("The quick".to_string(), false), (" fox".to_string(), true)],
+        false => [("The ".to_string(), false), ("quick fox".to_string(), true)],
+    };
+    assert_eq!(text(&x, &a), expected);
+
+    // bold grows as text is typed after it, while a link doesn’t
+    let link = x.add(&a, 10..13, "link", false, Expand::Neither).unwrap();
+    y.apply(&link);
+    for op in a.insert_str_at(13, "es") {
+        b.apply(&op);
+    }
+
+    let last = x.spans(&a).pop().unwrap();
+    assert_eq!(last.text, "es");
+    assert!(last.attributes.contains_key("bold"));
+    assert!(!last.attributes.contains_key("link"));
+
+    // removing the text at either end of a span leaves the rest of it formatted
+    let _ = b.remove_range(10..11);
+    assert_eq!(
+        y.spans(&b)
+            .iter()
+            .filter(|span| span.attributes.contains_key("link"))
+            .count(),
+        1
+    );
+    assert_eq!(
+        y.spans(&b)
+            .iter()
+            .map(|span| span.text.as_str())
+            .collect::<String>(),
+        "The quick oxes"
+    );
+}
+
+#[test]
+fn renumbered_sites() {
+    // two UUIDs given the same site id, until each learns of the other
+    let mut a = Storage::with_uuid(1 + u16::MAX as u128);
+    let mut b = Storage::with_uuid(1);
+    a.append("hello world".chars());
+
+    let mut x = Marks::default();
+    let bold = x.add(&a, 0..5, "bold", true, Expand::Neither).unwrap();
+
+    // a’s site moves out of the way of b’s, its positions along with it; the mark doesn’t mind
+    a.merge(&b);
+    let Anchor::Before(start) = &bold.start else {
+        unreachable!("a span that doesn’t expand starts before its first character")
+    };
+    assert_ne!(a.sites().site_id(a.uuid()), Some(start.site_id()));
+
+    let bolded = |marks: &Marks<bool>, storage: &Storage| {
+        marks
+            .spans(storage)
+            .into_iter()
+            .filter(|span| span.attributes.contains_key("bold"))
+            .map(|span| span.text)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(bolded(&x, &a), ["hello"]);
+
+    // and means the same at b, which learns of a’s site by its new id
+    b.merge(&a);
+    let mut y = Marks::default();
+    y.apply(&bold);
+    assert_eq!(bolded(&y, &b), ["hello"]);
+}
+
+#[cfg(test)]
+mod test {
+    use quickcheck_macros::quickcheck;
+
+    use super::*;
+
+    #[quickcheck]
+    fn spans_match_attributes(text: String, marks: Vec<(u8, u8, bool, Option<bool>)>) {
+        let mut storage = Storage::default();
+        storage.append(text.chars().filter(|ch| *ch != '\n'));
+        let len = storage.len();
+
+        let mut x = Marks::default();
+        for (start, end, italic, value) in marks {
+            let (start, end) = (start as usize % (len + 1), end as usize % (len + 1));
+            let name = if italic { "italic" } else { "bold" };
+            match value {
+                Some(value) => x.add(&storage, start..end, name, value, Expand::Right),
+                None => x.remove(&storage, start..end, name, Expand::Neither),
+            };
+        }
+
+        // every character takes on the formatting of its span
+        let spans = x.spans(&storage);
+        let expected = storage
+            .characters(..)
+            .map(|(pos, _)| x.attributes(&storage, pos));
+        let actual = spans
+            .iter()
+            .flat_map(|span| span.text.chars().map(|_| &span.attributes));
+        assert!(expected
+            .zip(actual)
+            .all(|(expected, actual)| expected == *actual));
+        assert_eq!(
+            spans
+                .iter()
+                .map(|span| span.text.chars().count())
+                .sum::<usize>(),
+            len
+        );
+        assert!(spans.windows(2).all(|w| w[0].attributes != w[1].attributes));
+    }
+}
@@ -0,0 +1,48 @@
+use crate::crdt::sites::Renames;
+use crate::{Element, Operation, Origin, Storage};
+
+/// A state-based CRDT: replicas converge by joining their complete states.
+///
+/// `merge` must be commutative, associative and idempotent so that
+/// replicas end up identical regardless of how (or how often) they sync.
+pub trait Merge {
+    /// Joins the state of `other` into `self`.
+    fn merge(&mut self, other: &Self);
+}
+
+impl<T: Element> Merge for Storage<T> {
+    /// The union of both replicas’ elements, less the union of their deletions.
+    ///
+    /// The sites of each are registered with the other first; so should `other` know of a site by
+    /// an id that has since moved, its positions are translated to the one it has here.
+    fn merge(&mut self, other: &Self) {
+        self.register(other.sites.iter().map(|(_, uuid)| uuid));
+
+        let ids: Renames = other
+            .sites
+            .iter()
+            .filter_map(|(site, uuid)| Some((site, self.sites.site_id(uuid)?)))
+            .filter(|(theirs, ours)| theirs != ours)
+            .collect();
+
+        self.version.merge(&other.version.renamed(&ids));
+
+        let mut ops = Vec::new();
+        for (pos, dot) in other.deleted.iter() {
+            let (pos, dot) = (pos.renamed(&ids), dot.renamed(&ids));
+            if self.remove_element(&pos, dot).is_some() {
+                ops.push(Operation::delete(pos, dot, &self.sites));
+            }
+        }
+
+        for (pos, value) in &other.elements {
+            // unless already deleted
+            let pos = pos.renamed(&ids);
+            if self.insert_element(pos.clone(), value.clone()).is_ok() {
+                ops.push(Operation::insert(pos, value.clone(), &self.sites));
+            }
+        }
+
+        self.notify(&ops, Origin::Remote);
+    }
+}
@@ -1,88 +1,132 @@
 use std::collections::btree_map::Entry;
 use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Bound::{Excluded, Unbounded};
+use std::sync::mpsc::Sender;
 
 use itertools::Itertools;
 
-pub use ranges::*;
+pub use awareness::*;
+pub use cursor::*;
+#[cfg(feature = "json")]
+pub use document::*;
+pub use element::*;
+pub use error::*;
+pub use events::*;
+pub use lines::*;
+pub use map::*;
+pub use marks::*;
+pub use merge::*;
+pub use ops::*;
+pub use sites::*;
+pub use transaction::*;
+pub use undo::*;
+pub use version::*;
 
-pub use crate::crdt::pos::Position;
+use crate::crdt::deleted::Deleted;
+use crate::crdt::index::{Index, Summary};
+pub use crate::crdt::pos::{InvalidPosition, Position};
 use crate::{crdt::pos::path, crdt::pos::path::algorithm::Algorithm, crdt::pos::path::Builder};
 
+pub mod wire;
+
+mod awareness;
+mod cursor;
+mod deleted;
+#[cfg(feature = "json")]
+mod document;
+mod element;
+mod error;
+mod events;
+mod index;
+mod lines;
+mod map;
+mod marks;
+mod merge;
+mod offsets;
+mod ops;
 mod pos;
 mod ranges;
+mod sites;
+mod transaction;
+mod undo;
+mod version;
+
+#[cfg(feature = "serde")]
+mod serde;
+
+#[cfg(test)]
+mod test;
 
-pub struct Storage {
-    characters: BTreeMap<Position, char>,
+/// A replicated sequence of elements; by default, the characters of a text document.
+///
+/// The document is bracketed by the [`Position::first()`] and [`Position::last()`]
+/// sentinels, which hold no element but can be used as the bounds of ranges and,
+/// in the case of [`Position::last()`], as the place to insert at the end.
+pub struct Storage<T = char> {
+    elements: BTreeMap<Position, T>,
     newlines: BTreeSet<Position>,
+    deleted: Deleted, // with the edits that deleted each position
+    version: VersionVector,
+    index: Index,
     algorithm: Algorithm,
-    clock: u16,
-    site: u16,
+    clock: u64,
+    transaction: Option<Option<Dot>>, // `Some` while one is open; with its dot, once it has one
+    site: u32,
+    uuid: u128,
+    sites: SiteRegistry,
+    observers: Vec<Sender<ChangeEvent>>,
 }
 
-impl Default for Storage {
+impl<T: Element> Default for Storage<T> {
     fn default() -> Self {
-        let mut characters = BTreeMap::default();
-        characters.insert(Position::first(), '\u{2402}');
-        characters.insert(Position::last(), '\u{2403}');
-
         let mut newlines = BTreeSet::default();
         newlines.insert(Position::first());
         newlines.insert(Position::last());
 
+        let uuid = sites::uuid();
+        let mut sites = SiteRegistry::default();
+
         Storage {
-            characters,
+            elements: Default::default(),
             newlines,
+            deleted: Default::default(),
+            version: Default::default(),
+            index: Default::default(),
             algorithm: Default::default(),
             clock: Default::default(),
-            site: Default::default(),
+            transaction: None,
+            site: sites.register(uuid),
+            uuid,
+            sites,
+            observers: Vec::new(),
         }
     }
 }
 
-impl FromIterator<char> for Storage {
-    fn from_iter<T: IntoIterator<Item = char>>(iter: T) -> Self {
+impl<T: Element> FromIterator<T> for Storage<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
         let mut new = Self::default();
 
-        let chars = iter.into_iter();
+        let elements = iter.into_iter();
+        let clock = new.next_clock();
         let positions = new
             .algorithm
-            .generate(&path::FIRST, &path::LAST)
-            .map(|path| (Position::new(new.site, new.clock, &path)));
+            .generate(new.site, &path::FIRST, &path::LAST)
+            .map(|path| Position::new(new.site, clock, &path))
+            .zip(elements)
+            .collect_vec();
 
-        let mut iter = positions.zip(chars);
-        new.characters.extend(iter.by_ref());
-        new.newlines
-            .extend(iter.filter(|(_, ch)| *ch == '\n').map(|(pos, _)| pos));
+        for (pos, value) in positions {
+            let _ = new.insert_element(pos, value);
+        }
 
         new
     }
 }
 
-impl Extend<char> for Storage {
-    fn extend<I: IntoIterator<Item = char>>(&mut self, iter: I) {
-        let chars = iter.into_iter();
-        let left = Builder::from_iter(
-            self.characters
-                .range(..)
-                .rev()
-                .skip(1) // skip `Position::last()` as is it an `Exclusive` bound
-                .map(|(pos, _)| pos.path())
-                .next()
-                .unwrap() // SAFETY: iterator will always have `Position::first()`
-                .iter()
-                .cloned(),
-        );
-
-        let clock = self.next_clock();
-        let positions = self
-            .algorithm
-            .generate(&left, &path::LAST)
-            .map(|path| (Position::new(self.site, clock, &path)));
-
-        let mut iter = positions.zip(chars);
-        self.characters.extend(iter.by_ref());
-        self.newlines
-            .extend(iter.filter(|(_, ch)| *ch == '\n').map(|(pos, _)| pos));
+impl<T: Element> Extend<T> for Storage<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        self.append(iter);
     }
 }
 
@@ -97,9 +141,13 @@ pub enum Strategy {
     /// bounder+ and boundary- at each level. Once a decision is
     /// made for a level it is always used (at that level).
     Boundaries(u32),
+    /// Kleppmann’s proposal: like boundary+, but each new run of characters is
+    /// nested beneath its left neighbour, tagged with the originating site, so
+    /// that concurrent runs typed at the same place are never interleaved.
+    NonInterleaving(u32),
 }
 
-impl Storage {
+impl<T: Element> Storage<T> {
     pub fn with_strategy(strategy: Strategy) -> Self {
         Storage {
             algorithm: Algorithm::with_strategy(strategy),
@@ -114,53 +162,322 @@ impl Storage {
         }
     }
 
-    #[inline(always)]
-    fn from(str: impl AsRef<str>) -> Self {
-        Self::from_iter(str.as_ref().chars())
+    /// Creates a replica for the site identified by `uuid`.
+    pub fn with_uuid(uuid: u128) -> Self {
+        let mut sites = SiteRegistry::default();
+
+        Storage {
+            site: sites.register(uuid),
+            uuid,
+            sites,
+            ..Default::default()
+        }
+    }
+
+    /// Returns the UUID that identifies this replica.
+    pub fn uuid(&self) -> u128 {
+        self.uuid
+    }
+
+    /// Returns the registry of every site that has edited this document.
+    pub fn sites(&self) -> &SiteRegistry {
+        &self.sites
+    }
+
+    /// Returns the element at `pos`.
+    pub fn get(&self, pos: &Position) -> Option<&T> {
+        self.elements.get(pos)
+    }
+
+    /// Returns whether the element at `pos` has been removed — here, or at a replica
+    /// whose operations or state have since been integrated.
+    ///
+    /// A deleted position is never reused, so an insert arriving after its own
+    /// delete is ignored.
+    pub fn is_deleted(&self, pos: &Position) -> bool {
+        self.deleted.contains(pos)
+    }
+
+    /// Appends the elements to the end of the document, returning the
+    /// [`Operation`]s needed to replicate them.
+    ///
+    /// Like [`Extend`], a single `clock` is allocated for the whole run.
+    pub fn append(&mut self, iter: impl IntoIterator<Item = T>) -> Vec<Operation<T>> {
+        let left = Builder::from(
+            self.elements
+                .keys()
+                .next_back()
+                .map_or(&path::FIRST[..], Position::path),
+        );
+
+        self.insert_run(&left, &path::LAST, iter)
     }
 
+    /// Inserts the elements between the `left` and `right` paths, all sharing a single `clock`.
+    fn insert_run(
+        &mut self,
+        left: &[u32],
+        right: &[u32],
+        iter: impl IntoIterator<Item = T>,
+    ) -> Vec<Operation<T>> {
+        let mut elements = iter.into_iter().peekable();
+        if elements.peek().is_none() {
+            return Vec::new(); // without using a `clock`, so that none are skipped
+        }
+
+        let clock = self.next_clock();
+        let positions = self
+            .algorithm
+            .generate(self.site, left, right)
+            .map(|path| Position::new(self.site, clock, &path))
+            .filter(|pos| !self.deleted.contains(pos)) // within a transaction, the `clock` may repeat
+            .zip(elements)
+            .collect_vec();
+
+        let ops: Vec<_> = positions
+            .into_iter()
+            .filter_map(|(pos, value)| {
+                self.insert_element(pos.clone(), value.clone())
+                    .map(|_| Operation::insert(pos, value, &self.sites))
+                    .ok()
+            })
+            .collect();
+
+        self.notify(&ops, Origin::Local);
+        ops
+    }
+
+    /// Inserts `value` immediately before the element at `before` — or at the end
+    /// of the document, if `before` is [`Position::last()`].
+    ///
+    /// See [`Storage::try_insert()`] for why it may fail.
     #[must_use]
-    pub fn insert(&mut self, ch: char, before: &Position) -> bool {
-        if let Some((right, left)) = self
-            .characters
-            .range(..=before)
-            .rev() // grab `pos` and its predecessor
-            .map(|(pos, _)| pos)
-            .tuple_windows()
-            .next()
-        {
-            if right == before {
-                let path = self.algorithm.generate_one(left.path(), right.path());
-                let pos = Position::new(self.site, self.next_clock(), &path);
-
-                return match self.characters.entry(pos) {
-                    Entry::Occupied(_) => false, // CRDTs do not replace values; positions must remain unique
-                    Entry::Vacant(entry) => {
-                        entry.insert(ch);
-                        true
-                    }
-                };
+    pub fn insert(&mut self, value: T, before: &Position) -> Option<Operation<T>> {
+        self.try_insert(value, before).ok()
+    }
+
+    /// Inserts `value` immediately before the element at `before`, as [`Storage::insert()`],
+    /// returning why it couldn’t be.
+    pub fn try_insert(&mut self, value: T, before: &Position) -> Result<Operation<T>, Error> {
+        if *before != Position::last() {
+            self.check(before)?;
+        }
+
+        let mut left = Builder::from(self.before(before).ok_or(Error::Sentinel)?.path());
+        let clock = self.upcoming_dot().clock; // only ticked once the insert can be made
+        let pos = loop {
+            let path = self.algorithm.generate_one(self.site, &left, before.path());
+            if path.len() > u16::MAX as usize {
+                return Err(Error::Exhausted);
             }
+
+            let pos = Position::new(self.site, clock, &path);
+            match self.deleted.contains(&pos) {
+                true => left = path, // within a transaction, the `clock` may repeat
+                false => break pos,
+            }
+        };
+
+        self.next_dot();
+        self.insert_element(pos.clone(), value.clone())?;
+
+        let op = Operation::insert(pos, value, &self.sites);
+        self.notify(std::slice::from_ref(&op), Origin::Local);
+        Ok(op)
+    }
+
+    /// Removes the element at `pos`, returning the [`Operation`] needed to replicate it.
+    ///
+    /// Returns `None` if there is no element at `pos`; the `Position::first()` and
+    /// `Position::last()` sentinels are never removed.
+    pub fn remove(&mut self, pos: &Position) -> Option<Operation<T>> {
+        self.try_remove(pos).ok()
+    }
+
+    /// Removes the element at `pos`, as [`Storage::remove()`], returning why it couldn’t be.
+    pub fn try_remove(&mut self, pos: &Position) -> Result<Operation<T>, Error> {
+        self.check(pos)?;
+
+        let dot = self.next_dot();
+        self.remove_element(pos, dot);
+
+        let op = Operation::delete(pos.clone(), dot, &self.sites);
+        self.notify(std::slice::from_ref(&op), Origin::Local);
+        Ok(op)
+    }
+
+    /// Checks that there is an element at `pos`, that can be edited.
+    fn check(&self, pos: &Position) -> Result<(), Error> {
+        if *pos <= Position::first() || *pos >= Position::last() {
+            Err(Error::Sentinel)
+        } else if self.deleted.contains(pos) {
+            Err(Error::Deleted)
+        } else if !self.elements.contains_key(pos) {
+            Err(Error::UnknownPosition)
+        } else {
+            Ok(())
         }
+    }
 
-        false
+    /// Returns the position of the element before `pos` — or [`Position::first()`] — unless `pos` is
+    /// [`Position::first()`] itself.
+    fn before(&self, pos: &Position) -> Option<&Position> {
+        match self.elements.range(..pos).next_back() {
+            Some((left, _)) => Some(left),
+            None => self.newlines.first().filter(|first| *first < pos),
+        }
     }
 
-    pub fn remove(&mut self, pos: &Position) -> bool {
-        self.characters
-            .remove(pos)
-            .filter(|ch| *ch == '\n')
-            .map(|_| self.newlines.remove(pos))
-            .unwrap_or(false)
+    /// Returns [`Position::last()`], borrowed from the `newlines` index which always holds it.
+    fn last(&self) -> &Position {
+        self.newlines.last().unwrap() // SAFETY: the sentinels are never removed
+    }
+
+    /// Inserts `value` at `pos`, keeping the `newlines` and order-statistic indices up-to-date.
+    fn insert_element(&mut self, pos: Position, value: T) -> Result<(), Error> {
+        if pos <= Position::first() || pos >= Position::last() {
+            return Err(Error::Sentinel); // the sentinels bracket the document and every line
+        }
+
+        if self.deleted.contains(&pos) {
+            return Err(Error::Deleted); // its delete arrived first
+        }
+
+        let pos = match self.elements.entry(pos) {
+            Entry::Occupied(_) => return Err(Error::Duplicate), // CRDTs do not replace values; positions must remain unique
+            Entry::Vacant(entry) => {
+                let pos = entry.key().clone();
+                entry.insert(value);
+                pos
+            }
+        };
+
+        let value = &self.elements[&pos];
+        if value.is_newline() {
+            self.newlines.insert(pos.clone());
+        }
+
+        let summary = Summary {
+            chars: 1,
+            graphemes: self.begins_grapheme(&pos) as usize,
+            newlines: value.is_newline() as usize,
+            utf8: value.len_utf8(),
+            utf16: value.len_utf16(),
+        };
+
+        self.index.insert(pos.clone(), summary);
+        self.regraph(&pos);
+        Ok(())
+    }
+
+    /// Removes the element at `pos`, by the edit identified by `dot`, keeping the `newlines` and
+    /// order-statistic indices up-to-date.
+    ///
+    /// The deletion is remembered, even of an element yet to arrive, so that [`Merge`] and
+    /// [`Storage::operations_since()`] can propagate it.
+    fn remove_element(&mut self, pos: &Position, dot: Dot) -> Option<T> {
+        if *pos <= Position::first() || *pos >= Position::last() {
+            return None; // the sentinels bracket the document and every line
+        }
+
+        self.deleted.insert(pos, dot);
+
+        let value = self.elements.remove(pos)?;
+        if value.is_newline() {
+            self.newlines.remove(pos);
+        }
+
+        self.index.remove(pos);
+        self.regraph(pos);
+
+        Some(value)
+    }
+
+    /// Whether a grapheme cluster begins at (the existing) `pos`, judging by the few elements before it.
+    fn begins_grapheme(&self, pos: &Position) -> bool {
+        let mut context = self
+            .elements
+            .range(..pos)
+            .rev()
+            .take(T::CONTEXT)
+            .map(|(_, value)| value.clone())
+            .collect_vec();
+        context.reverse();
+
+        self.elements[pos].begins_grapheme(&context)
+    }
+
+    /// Re-evaluates the grapheme boundaries of the elements following `pos`, for as long as they change.
+    fn regraph(&mut self, pos: &Position) {
+        let following = self
+            .elements
+            .range((Excluded(pos), Unbounded))
+            .take(T::CONTEXT)
+            .map(|(pos, _)| pos.clone())
+            .collect_vec();
+
+        for pos in following {
+            let graphemes = self.begins_grapheme(&pos) as usize;
+
+            // SAFETY: every element is in the `index`
+            let summary = self.index.get(&pos).unwrap();
+            if summary.graphemes == graphemes {
+                break;
+            }
+
+            self.index.update(
+                &pos,
+                Summary {
+                    graphemes,
+                    ..summary
+                },
+            );
+        }
     }
 
     #[inline]
     /// The `clock` is incremented every insert to avoid the
     /// [ABA problem](https://en.wikipedia.org/wiki/ABA_problem)
     /// inherent in an insert-delete-insert at the same location.
-    fn next_clock(&mut self) -> u16 {
-        self.clock = u16::wrapping_add(self.clock, 1);
-        self.clock
+    /// At 64 bits, it never wraps around. Within a transaction, where it is
+    /// incremented just once, new positions skip over those it has deleted instead.
+    fn next_clock(&mut self) -> u64 {
+        self.next_dot().clock
+    }
+
+    /// Returns the [`Dot`] that [`Storage::next_dot()`] would, without ticking the `clock`.
+    fn upcoming_dot(&self) -> Dot {
+        if let Some(Some(dot)) = self.transaction {
+            return dot;
+        }
+
+        // past any edit of its own it has heard of since; as a replica restored from an older
+        // snapshot, or one that shares its UUID, would otherwise reuse their dots
+        Dot {
+            site: self.site,
+            clock: Ord::max(self.clock, self.version.get(self.site)) + 1,
+        }
+    }
+
+    /// Ticks the `clock`, returning the [`Dot`] that identifies the new edit; or that of the open
+    /// transaction, if it has already ticked.
+    fn next_dot(&mut self) -> Dot {
+        let dot = self.upcoming_dot();
+        self.clock = dot.clock;
+
+        if let Some(transaction) = &mut self.transaction {
+            *transaction = Some(dot);
+        }
+
+        self.version.observe(dot);
+        dot
+    }
+}
+
+impl Storage {
+    #[inline(always)]
+    fn from(str: impl AsRef<str>) -> Self {
+        Self::from_iter(str.as_ref().chars())
     }
 }
 
@@ -169,23 +486,100 @@ fn invalid_insert_position() {
     let mut storage = crate::Storage::with_strategy(Strategy::Boundary);
 
     // inserting before `Position::first()` always fails
-    assert_eq!(storage.insert('d', &Position::first()), false);
+    assert!(storage.insert('d', &Position::first()).is_none());
 
     let str = "abc";
     storage.extend(str.chars());
 
     // Note, that even with a gap between keys…
-    let pos = Position::new(0, storage.clock, &[5]);
-    storage.characters.insert(pos, 'e');
+    let pos = Position::new(0, storage.clock, &[6]);
+    let _ = storage.insert_element(pos, 'e');
 
     // attempting to insert before a non-existent key fails…
-    let pos = Position::new(0, storage.clock, &[4]);
-    assert_eq!(storage.insert('d', &pos), false);
+    let pos = Position::new(0, storage.clock, &[5]);
+    assert!(storage.insert('d', &pos).is_none());
 
     // while using the appropriate key works.
-    let pos = Position::new(0, storage.clock, &[5]);
-    assert_eq!(storage.insert('d', &pos), true);
+    let pos = Position::new(0, storage.clock, &[6]);
+    assert!(storage.insert('d', &pos).is_some());
 
     let string = storage.string(..);
     assert_eq!(string, "abcde");
 }
+
+#[test]
+fn removing_characters() {
+    let mut storage = Storage::from("a\nb\nc");
+    let positions = storage
+        .characters(..)
+        .map(|(pos, _)| pos.clone())
+        .collect_vec();
+
+    // the sentinels are never removed, directly or by a remote operation
+    assert!(storage.remove(&Position::first()).is_none());
+    assert!(storage.remove(&Position::last()).is_none());
+    assert!(!storage.apply(&Operation::Delete {
+        pos: Position::last(),
+        dot: Default::default(),
+        sites: Default::default(),
+    }));
+
+    // every character reports its removal, newline or not
+    let op = storage.remove(&positions[0]);
+    assert_eq!(op.as_ref().map(Operation::position), Some(&positions[0]));
+    assert!(storage.remove(&positions[0]).is_none());
+
+    assert!(storage.remove(&positions[1]).is_some());
+    assert_eq!(storage.line_count(), 2);
+    assert_eq!(storage.lines(..).count(), 2);
+
+    assert!(storage.remove(&positions[3]).is_some());
+    assert_eq!(storage.line_count(), 1);
+    assert_eq!(storage.string(..), "bc");
+}
+
+#[test]
+fn clocks_never_wrap() {
+    let mut storage = Storage {
+        clock: u16::MAX as u64 - 1,
+        ..Default::default()
+    };
+
+    let mut ops = Vec::new();
+    for ch in "abc".chars() {
+        ops.extend(storage.append([ch]));
+    }
+
+    // the last two no longer fit in 16 bits, yet remain distinct
+    let clocks = ops.iter().map(|op| op.dot().clock).collect_vec();
+    assert_eq!(clocks, [0xffff, 0x10000, 0x10001]);
+    assert!(ops[1].position().is_heap());
+
+    let bytes = wire::encode(&ops).unwrap();
+    let mut replica = Storage::default();
+    for op in wire::decode(&bytes, &mut replica).unwrap() {
+        assert!(replica.apply(&op));
+    }
+
+    assert_eq!(replica.string(..), "abc");
+    assert_eq!(replica.version().get(storage.site), 0x10001);
+}
+
+#[test]
+fn exhausted_paths() {
+    let mut storage = Storage::with_uuid(1);
+
+    // neighbours as deep as a path can go, with no room between them at the last level
+    let mut path = vec![2; u16::MAX as usize];
+    let left = Position::new(storage.site, 1, &path);
+    *path.last_mut().unwrap() = 3;
+    let right = Position::new(storage.site, 1, &path);
+    storage.insert_element(left, 'a').unwrap();
+    storage.insert_element(right.clone(), 'b').unwrap();
+
+    // the insert fails without using up a dot, which a replica would wait for in vain
+    let (clock, version) = (storage.clock, storage.version().clone());
+    assert_eq!(storage.try_insert('c', &right), Err(Error::Exhausted));
+    assert_eq!(storage.clock, clock);
+    assert_eq!(storage.version(), &version);
+}
@@ -0,0 +1,213 @@
+use std::ops::Range;
+
+use crate::crdt::index::Metric;
+use crate::crdt::pos::path::Builder;
+use crate::{Element, Operation, Origin, Position, Storage};
+
+impl<T: Element> Storage<T> {
+    /// Returns the number of elements in the document.
+    pub fn len(&self) -> usize {
+        self.index.total().chars
+    }
+
+    /// Returns whether the document has no elements.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the [`Position`] of the element at `index`, or [`Position::last()`]
+    /// when `index` is the length of the document (i.e. the insertion point at its end).
+    pub fn position_at(&self, index: usize) -> Option<&Position> {
+        match self.index.select(Metric::Chars, index) {
+            None if index == self.len() => Some(self.last()),
+            pos => pos,
+        }
+    }
+
+    /// Returns the index of the element at `pos`.
+    pub fn offset_of(&self, pos: &Position) -> Option<usize> {
+        self.index.get(pos).map(|_| self.index.rank(pos).chars)
+    }
+
+    /// Inserts `value` so that it becomes the element at `index`.
+    ///
+    /// Returns `None` if `index` is past the end of the document.
+    pub fn insert_at(&mut self, index: usize, value: T) -> Option<Operation<T>> {
+        let before = self.position_at(index)?.clone();
+        self.insert(value, &before)
+    }
+
+    /// Inserts the elements so that they start at `index`.
+    ///
+    /// Like [`Storage::append`], a single `clock` is allocated for the whole run.
+    /// Returns no operations if `index` is past the end of the document.
+    pub fn insert_all_at(
+        &mut self,
+        index: usize,
+        iter: impl IntoIterator<Item = T>,
+    ) -> Vec<Operation<T>> {
+        let Some(right) = self.position_at(index).cloned() else {
+            return Vec::new();
+        };
+
+        // SAFETY: `Position::first()` is before any other position
+        let left = Builder::from(self.before(&right).unwrap().path());
+        self.insert_run(&left, right.path(), iter)
+    }
+
+    /// Removes the element at `index`.
+    pub fn remove_at(&mut self, index: usize) -> Option<Operation<T>> {
+        let pos = self.index.select(Metric::Chars, index)?.clone();
+        self.remove(&pos)
+    }
+
+    /// Removes the elements within the `range` of indices.
+    ///
+    /// Like [`Storage::remove_at()`], removes nothing, and returns no operations, if the `range`
+    /// runs past the end of the document; rather than just the elements before its end.
+    pub fn remove_range(&mut self, range: Range<usize>) -> Vec<Operation<T>> {
+        if range.end > self.len() {
+            return Vec::new();
+        }
+
+        let Some(start) = self.index.select(Metric::Chars, range.start) else {
+            return Vec::new();
+        };
+
+        let positions: Vec<_> = self
+            .elements(start..)
+            .take(range.len())
+            .map(|(pos, _)| pos.clone())
+            .collect();
+
+        if positions.is_empty() {
+            return Vec::new();
+        }
+
+        // like an insert of a run, the whole range is removed by a single edit
+        let dot = self.next_dot();
+        let ops: Vec<_> = positions
+            .into_iter()
+            .filter_map(|pos| {
+                self.remove_element(&pos, dot)
+                    .map(|_| Operation::delete(pos, dot, &self.sites))
+            })
+            .collect();
+
+        self.notify(&ops, Origin::Local);
+        ops
+    }
+}
+
+impl Storage {
+    /// Returns the (zero-based) line that `pos` is on.
+    ///
+    /// A newline is on the line that it ends. The `pos` need not be in the document;
+    /// removed positions are still ordered with respect to the remaining ones.
+    pub fn line_of(&self, pos: &Position) -> usize {
+        self.index.rank(pos).newlines
+    }
+
+    /// Returns the newline that starts the (zero-based) `line` — or [`Position::first()`]
+    /// for the first line — in the same way as the pairs returned by [`Storage::lines()`].
+    pub fn line_start(&self, line: usize) -> Option<&Position> {
+        match line {
+            0 => self.newlines.first(),
+            _ => self.index.select(Metric::Newlines, line - 1),
+        }
+    }
+
+    /// Inserts the string so that it starts at `index`.
+    ///
+    /// Like [`Storage::append`], a single `clock` is allocated for the whole string.
+    /// Returns no operations if `index` is past the end of the document.
+    pub fn insert_str_at(&mut self, index: usize, str: &str) -> Vec<Operation> {
+        self.insert_all_at(index, str.chars())
+    }
+}
+
+#[test]
+fn offset_editing() {
+    let mut storage = Storage::default();
+    let mut expected = String::new();
+
+    storage.insert_str_at(0, "hello world");
+    expected.insert_str(0, "hello world");
+
+    assert!(storage.insert_at(5, ',').is_some());
+    expected.insert(5, ',');
+
+    assert_eq!(storage.insert_str_at(12, "wide ").len(), 5);
+    expected.insert_str(12, "wide ");
+
+    assert!(storage.remove_at(0).is_some());
+    expected.remove(0);
+
+    assert_eq!(storage.remove_range(4..6).len(), 2);
+    expected.replace_range(4..6, "");
+
+    storage.insert_str_at(storage.characters(..).count(), "!");
+    expected.push('!');
+
+    assert_eq!(storage.string(..), expected);
+
+    // past the end
+    let len = expected.len();
+    assert!(storage.insert_at(len + 1, '?').is_none());
+    assert!(storage.insert_str_at(len + 1, "?").is_empty());
+    assert!(storage.remove_at(len).is_none());
+    assert!(storage.remove_range(len..len + 2).is_empty());
+
+    // nor is a range that runs past it cut short
+    assert!(storage.remove_range(len - 2..len + 1).is_empty());
+    assert_eq!(storage.string(..), expected);
+}
+
+#[test]
+fn offsets_and_lines() {
+    let storage = Storage::from("one\ntwo\n\nfour");
+
+    for (n, (pos, _)) in storage.characters(..).enumerate() {
+        assert_eq!(storage.position_at(n), Some(pos));
+        assert_eq!(storage.offset_of(pos), Some(n));
+    }
+
+    assert_eq!(storage.position_at(storage.len()), Some(&Position::last()));
+    assert_eq!(storage.position_at(storage.len() + 1), None);
+    assert_eq!(storage.offset_of(&Position::first()), None);
+
+    let lines = storage
+        .lines(..)
+        .map(|(start, _)| start)
+        .collect::<Vec<_>>();
+    for (n, start) in lines.iter().enumerate() {
+        assert_eq!(storage.line_start(n), Some(*start));
+    }
+
+    assert_eq!(storage.line_start(lines.len()), None);
+    assert_eq!(storage.line_of(storage.position_at(5).unwrap()), 1);
+    assert_eq!(storage.line_of(&Position::last()), 3);
+}
+
+#[test]
+fn grapheme_counts() {
+    let mut storage = Storage::from("e\u{301}👧🏻🇬🇧🇯🇵");
+    assert_eq!(
+        storage.index.total().graphemes,
+        storage.graphemes(..).count()
+    );
+
+    // removing the modifier splits `👧🏻` into two graphemes
+    let _ = storage.remove_at(3);
+    assert_eq!(
+        storage.index.total().graphemes,
+        storage.graphemes(..).count()
+    );
+
+    // and inserting a regional indicator re-pairs the flags that follow it
+    let _ = storage.insert_at(3, '\u{1F1FA}');
+    assert_eq!(
+        storage.index.total().graphemes,
+        storage.graphemes(..).count()
+    );
+}
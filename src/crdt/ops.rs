@@ -0,0 +1,295 @@
+use crate::crdt::sites::Renames;
+use crate::{Claims, Dot, Element, Error, Origin, Position, SiteRegistry, Storage, UnknownSite};
+
+/// An edit, made at one site, that can be shipped to and integrated by the others.
+///
+/// Along with the site ids it mentions, it carries the UUIDs they belong to; which the replica
+/// that integrates it registers, and translates them to the ids they have there.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Operation<T = char> {
+    /// An element inserted at a newly generated [`Position`].
+    Insert {
+        pos: Position,
+        value: T,
+        sites: Claims,
+    },
+    /// The removal of the element at [`Position`], by the edit identified by the [`Dot`].
+    Delete {
+        pos: Position,
+        dot: Dot,
+        sites: Claims,
+    },
+}
+
+impl<T> Operation<T> {
+    /// An insert of `value` at `pos`, made by a replica that knows of the `sites`.
+    pub(crate) fn insert(pos: Position, value: T, sites: &SiteRegistry) -> Self {
+        Operation::Insert {
+            sites: sites.claims([pos.site_id()]),
+            pos,
+            value,
+        }
+    }
+
+    /// A delete of `pos` by the edit `dot`, made by a replica that knows of the `sites`.
+    pub(crate) fn delete(pos: Position, dot: Dot, sites: &SiteRegistry) -> Self {
+        Operation::Delete {
+            sites: sites.claims([pos.site_id(), dot.site]),
+            pos,
+            dot,
+        }
+    }
+
+    /// Returns the UUIDs of the sites this operation mentions.
+    pub fn sites(&self) -> &Claims {
+        match self {
+            Operation::Insert { sites, .. } => sites,
+            Operation::Delete { sites, .. } => sites,
+        }
+    }
+
+    /// Returns the [`Position`] this operation inserts at, or deletes.
+    pub fn position(&self) -> &Position {
+        match self {
+            Operation::Insert { pos, .. } => pos,
+            Operation::Delete { pos, .. } => pos,
+        }
+    }
+
+    /// Returns the [`Dot`] that identifies the edit this operation is (part of).
+    pub fn dot(&self) -> Dot {
+        match self {
+            Operation::Insert { pos, .. } => pos.dot(),
+            Operation::Delete { dot, .. } => *dot,
+        }
+    }
+}
+
+impl<T: Element> Storage<T> {
+    /// Integrates an [`Operation`] generated by another replica.
+    ///
+    /// Returns whether the document changed. Operations are idempotent — applying
+    /// one a second time is a no-op — and, as every [`Position`] is unique and totally
+    /// ordered, concurrent inserts give the same document in whatever order they arrive.
+    /// Nor does a delete need to arrive after the insert it removes.
+    ///
+    /// The sites the operation mentions are registered first; so its site ids needn’t mean the
+    /// same here as at the replica that made it.
+    pub fn apply(&mut self, op: &Operation<T>) -> bool {
+        self.try_apply(op).is_ok()
+    }
+
+    /// Integrates an [`Operation`], as [`Storage::apply()`], returning why the document didn’t change.
+    ///
+    /// A delete that arrives before its insert is [`Error::UnknownPosition`], although it is
+    /// remembered all the same. Only an [`Error::Sentinel`] is turned away unseen; as no replica
+    /// would have made it, its [`Dot`] can’t be trusted not to skip past edits yet to arrive. Nor,
+    /// of course, is an [`Error::UnknownSite`].
+    pub fn try_apply(&mut self, op: &Operation<T>) -> Result<(), Error> {
+        let (op, _) = self.localize(op)?;
+        self.integrate(&op)
+    }
+
+    /// Registers the sites that `op` mentions, returning it with the ids they have here; along
+    /// with the sites that were moved to make room.
+    pub(crate) fn localize(
+        &mut self,
+        op: &Operation<T>,
+    ) -> Result<(Operation<T>, Renames), UnknownSite> {
+        let renames = self.register(op.sites().iter().map(|(_, uuid)| uuid));
+        let site = |site: u32| {
+            op.sites()
+                .uuid(site)
+                .and_then(|uuid| self.sites.site_id(uuid))
+                .ok_or(UnknownSite(site))
+        };
+
+        let pos = op.position();
+        let pos = match site(pos.site_id())? {
+            id if id == pos.site_id() => pos.clone(),
+            id => Position::new(id, pos.clock(), pos.path()),
+        };
+
+        let op = match op {
+            Operation::Insert { value, .. } => Operation::insert(pos, value.clone(), &self.sites),
+            Operation::Delete { dot, .. } => {
+                let dot = Dot {
+                    site: site(dot.site)?,
+                    clock: dot.clock,
+                };
+                Operation::delete(pos, dot, &self.sites)
+            }
+        };
+
+        Ok((op, renames))
+    }
+
+    /// Integrates an operation whose site ids have been [localized](Storage::localize).
+    pub(crate) fn integrate(&mut self, op: &Operation<T>) -> Result<(), Error> {
+        let result = match op {
+            Operation::Insert { pos, value, .. } => self.insert_element(pos.clone(), value.clone()),
+            Operation::Delete { pos, dot, .. } => {
+                let deleted = self.deleted.contains(pos);
+                match self.remove_element(pos, *dot) {
+                    Some(_) => Ok(()),
+                    None if *pos <= Position::first() || *pos >= Position::last() => {
+                        Err(Error::Sentinel)
+                    }
+                    None if deleted => Err(Error::Deleted),
+                    None => Err(Error::UnknownPosition),
+                }
+            }
+        };
+
+        if result != Err(Error::Sentinel) {
+            self.version.observe(op.dot());
+        }
+
+        result?;
+        self.notify(std::slice::from_ref(op), Origin::Remote);
+        Ok(())
+    }
+}
+
+#[test]
+fn remote_operations() {
+    let mut a = Storage::with_uuid(1);
+    let mut b = Storage::with_uuid(2);
+
+    for op in a.append("held".chars()) {
+        assert!(b.apply(&op));
+    }
+
+    // applying the same operation twice has no effect
+    let pos = a.characters(..).nth(3).map(|(pos, _)| pos.clone()).unwrap();
+    let op = a.insert('l', &pos).unwrap();
+    assert!(b.apply(&op));
+    assert!(!b.apply(&op));
+
+    // concurrent inserts converge regardless of their order of arrival
+    let pos = a.characters(..).nth(4).map(|(pos, _)| pos.clone()).unwrap();
+    let x = a.insert('o', &pos).unwrap();
+    let y = b.insert('!', &pos).unwrap();
+    assert!(a.apply(&y));
+    assert!(b.apply(&x));
+
+    let pos = a.characters(..).next().map(|(pos, _)| pos.clone()).unwrap();
+    let op = a.remove(&pos).unwrap();
+    assert!(b.apply(&op));
+    assert!(!b.apply(&op));
+
+    assert_eq!(a.string(..), b.string(..));
+}
+
+#[test]
+fn operations_out_of_order() {
+    use crate::Merge;
+
+    let mut a = Storage::with_uuid(1);
+    let mut b = Storage::with_uuid(2);
+
+    let ops = a.append("abc".chars());
+    let pos = ops[1].position().clone();
+    let delete = a.remove(&pos).unwrap();
+
+    // the delete overtakes its insert, which then isn’t resurrected
+    assert!(!b.apply(&delete));
+    assert!(b.is_deleted(&pos));
+    for op in ops.iter().rev() {
+        b.apply(op);
+    }
+
+    assert_eq!(b.string(..), "ac");
+    assert_eq!(a.string(..), b.string(..));
+
+    // nor is a deleted position revived by a merge, or a repeated insert
+    let mut c = Storage::with_uuid(3);
+    for op in &ops {
+        c.apply(op);
+    }
+    b.merge(&c);
+    assert!(!b.apply(&ops[1]));
+    assert_eq!(b.string(..), "ac");
+
+    c.merge(&b);
+    assert_eq!(c.string(..), "ac");
+    assert!(c.is_deleted(&pos));
+
+    // inserts concurrent with a delete are kept
+    let x = c.insert('x', &ops[2].position().clone()).unwrap();
+    let y = b.remove(ops[2].position()).unwrap();
+    assert!(b.apply(&x));
+    assert!(c.apply(&y));
+    assert_eq!(b.string(..), "ax");
+    assert_eq!(c.string(..), "ax");
+}
+
+#[test]
+fn clocks_only_move_forward() {
+    use crate::Dot;
+
+    let mut a = Storage::with_uuid(1);
+    let ops = a.append("hi".chars());
+
+    // an operation turned away as invalid isn’t taken to have been seen…
+    let dot = Dot {
+        site: a.site,
+        clock: 100,
+    };
+    let bogus = Operation::delete(Position::last(), dot, a.sites());
+    assert_eq!(a.try_apply(&bogus), Err(Error::Sentinel));
+    assert!(!a.version().includes(bogus.dot()));
+
+    // …while a replica that lost its own edits, and hears of them again, never reuses their dots
+    let mut restored = Storage::with_uuid(1);
+    for op in &ops {
+        restored.apply(op);
+    }
+
+    let op = restored.append("!".chars()).remove(0);
+    assert!(op.dot().clock > ops[0].dot().clock);
+    assert!(!a.version().includes(op.dot()));
+}
+
+#[test]
+fn colliding_sites() {
+    use crate::Strategy;
+
+    // two UUIDs given the same site id, until each learns of the other
+    let replica = |uuid| {
+        let mut sites = SiteRegistry::default();
+        Storage {
+            site: sites.register(uuid),
+            uuid,
+            sites,
+            ..Storage::with_strategy(Strategy::Boundary)
+        }
+    };
+    let mut a = replica(1);
+    let mut b = replica(1 + u16::MAX as u128);
+    assert_eq!(a.site, b.site);
+
+    let ops = a.append("ab".chars());
+    let theirs = b.append("xy".chars());
+    for op in &ops {
+        assert_eq!(b.try_apply(op), Ok(()));
+    }
+    for op in &theirs {
+        assert_eq!(a.try_apply(op), Ok(()));
+    }
+
+    // each learnt the other’s UUID from its operations, and moved it out of the way
+    assert_eq!(a.sites(), b.sites());
+    assert_ne!(a.site, b.site);
+    assert_eq!(a.string(..), b.string(..));
+    assert_eq!(a.len(), 4);
+
+    // an operation that mentions a site without claiming its UUID is turned away
+    let mut op = a.remove_at(0).unwrap();
+    if let Operation::Delete { sites, .. } = &mut op {
+        *sites = Claims::default();
+    }
+    let site = op.dot().site;
+    assert_eq!(b.localize(&op).err(), Some(UnknownSite(site)));
+    assert_eq!(b.try_apply(&op), Err(Error::UnknownSite));
+}
@@ -1,7 +1,5 @@
 #![allow(unsafe_code)]
 
-pub use traits::*;
-
 pub mod path;
 mod traits;
 
@@ -9,10 +7,17 @@ mod traits;
 mod test;
 
 #[cfg(feature = "serde")]
-mod serde;
+pub(crate) mod serde;
 
 const INLINE: usize = 3;
 
+/// A `Large` position whose `site` or `clock` is too wide for its field; the heap holds
+/// both, ahead of the path.
+const WIDE: u8 = 0b1;
+
+/// The number of 32-bit words a wide `site` and `clock` take on the heap.
+const STAMP: usize = 3;
+
 #[repr(C)]
 #[derive(Copy, Clone, Default)]
 struct Small {
@@ -24,10 +29,10 @@ struct Small {
 #[repr(C)]
 #[derive(Copy, Clone)]
 struct Large {
-    site: u16,
-    clock: u16,
+    site: u16,   // unused when `WIDE`
+    clock: u16,  // unused when `WIDE`
     length: u16, // up to 2¹⁶ 32-bit words
-    pad: u8,
+    flags: u8,
     tag: u8, // 0xff
     path: *const u32,
 }
@@ -40,35 +45,44 @@ pub union Position {
 }
 
 impl Position {
-    pub(crate) fn new(site: u16, clock: u16, path: &[u32]) -> Position {
+    pub(crate) fn new(site: u32, clock: u64, path: &[u32]) -> Position {
         let len: u16 = path.len() as u16;
 
         let mut new = Position {
             small: Small {
-                site,
-                clock,
+                site: site as u16,
+                clock: clock as u16,
                 ..Default::default()
             },
         };
 
+        let wide = site > u16::MAX as u32 || clock > u16::MAX as u64;
+
         unsafe {
-            if len as usize <= INLINE {
+            if len as usize <= INLINE && !wide {
                 std::ptr::copy_nonoverlapping(
                     path.as_ptr(),
                     new.small.path.as_mut_ptr(),
                     len as usize,
                 )
             } else {
-                let layout = std::alloc::Layout::array::<u32>(len as usize).unwrap();
+                let offset = if wide { STAMP } else { 0 };
+                let layout = std::alloc::Layout::array::<u32>(offset + len as usize).unwrap();
                 let ptr = std::alloc::alloc(layout) as *mut u32;
 
                 if ptr.is_null() {
                     std::alloc::handle_alloc_error(layout);
                 }
 
-                std::ptr::copy_nonoverlapping(path.as_ptr(), ptr, len as usize);
+                if wide {
+                    let words = [site, clock as u32, (clock >> 32) as u32];
+                    std::ptr::copy_nonoverlapping(words.as_ptr(), ptr, STAMP);
+                }
+
+                std::ptr::copy_nonoverlapping(path.as_ptr(), ptr.add(offset), len as usize);
                 new.large.path = ptr;
                 new.large.length = len;
+                new.large.flags = if wide { WIDE } else { 0 };
                 new.large.tag = 0xff; // tag it last for `Drop` safety
             }
         }
@@ -77,10 +91,59 @@ impl Position {
     }
 }
 
+/// Why a path, read from outside of the document, can’t be made into a [`Position`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum InvalidPosition {
+    /// Level zero holds the value reserved for the `last` sentinel.
+    Reserved,
+    /// Level zero is past the `last` sentinel; where the heap tag lives.
+    OutOfBounds(u32),
+    /// The level is zero, which an inline path couldn’t tell apart from its padding.
+    Zero(usize),
+    /// The path has more levels than a `Position` can hold.
+    TooLong(usize),
+    /// The path is that of a sentinel, which only the sentinel itself — with site and clock `0` — has.
+    Sentinel,
+}
+
+impl Position {
+    /// Creates a `Position` from an untrusted `path`, created by the `site` at the time of its `clock`.
+    ///
+    /// Only paths that the document could have generated itself — or those of its sentinels —
+    /// are accepted, as a level zero that overlaps the heap tag would be mistaken for a pointer.
+    pub fn try_new(site: u32, clock: u64, path: &[u32]) -> Result<Position, InvalidPosition> {
+        Position::validate(site, clock, path)?;
+        Ok(Position::new(site, clock, path))
+    }
+
+    pub(crate) fn validate(site: u32, clock: u64, path: &[u32]) -> Result<(), InvalidPosition> {
+        if path.is_empty() || path == path::LAST {
+            return match (site, clock) {
+                (0, 0) => Ok(()),
+                _ => Err(InvalidPosition::Sentinel),
+            };
+        }
+
+        if path.len() > u16::MAX as usize {
+            return Err(InvalidPosition::TooLong(path.len()));
+        }
+
+        if let Some(level) = path.iter().position(|n| *n == 0) {
+            return Err(InvalidPosition::Zero(level));
+        }
+
+        match path.first().copied() {
+            Some(n) if n > Position::end_bound(0) => Err(InvalidPosition::OutOfBounds(n)),
+            Some(n) if n == Position::end_bound(0) => Err(InvalidPosition::Reserved),
+            _ => Ok(()),
+        }
+    }
+}
+
 impl Clone for Position {
     /// # Safety
     ///
-    /// The [`Position`] must have been created with [`Position::new()`]
+    /// The [`Position`] must have been created with `Position::new()`
     /// to guarantee that is is correctly tagged as `is_heap` or `is_inline`.    
     ///
     fn clone(&self) -> Self {
@@ -88,14 +151,15 @@ impl Clone for Position {
             if self.is_inline() {
                 Position { small: self.small }
             } else {
-                let layout = std::alloc::Layout::array::<u32>(self.large.length as usize).unwrap();
+                let words = self.heap_words();
+                let layout = std::alloc::Layout::array::<u32>(words).unwrap();
                 let ptr = std::alloc::alloc(layout) as *mut u32;
 
                 if ptr.is_null() {
                     std::alloc::handle_alloc_error(layout);
                 }
 
-                std::ptr::copy_nonoverlapping(self.large.path, ptr, self.large.length as usize);
+                std::ptr::copy_nonoverlapping(self.large.path, ptr, words);
 
                 Position {
                     large: Large {
@@ -111,13 +175,13 @@ impl Clone for Position {
 impl Drop for Position {
     /// # Safety
     ///
-    /// The [`Position`] must have been created with [`Position::new()`]
+    /// The [`Position`] must have been created with `Position::new()`
     /// to guarantee that is is correctly tagged as `is_heap` or `is_inline`.    
     ///
     fn drop(&mut self) {
         unsafe {
             if self.is_heap() {
-                let layout = std::alloc::Layout::array::<u32>(self.large.length as usize).unwrap();
+                let layout = std::alloc::Layout::array::<u32>(self.heap_words()).unwrap();
                 std::alloc::dealloc(self.large.path.cast_mut() as *mut u8, layout);
             }
         }
@@ -127,14 +191,40 @@ impl Drop for Position {
 impl Position {
     #[inline]
     /// Returns the site id for this Position.
-    pub fn site_id(&self) -> u16 {
-        unsafe { self.small.site }
+    pub fn site_id(&self) -> u32 {
+        unsafe {
+            if self.is_wide() {
+                *self.large.path
+            } else {
+                self.small.site as u32
+            }
+        }
     }
 
     #[inline]
     /// Returns the timestamp for when this Position was created.
-    pub(crate) fn clock(&self) -> u16 {
-        unsafe { self.small.clock }
+    pub(crate) fn clock(&self) -> u64 {
+        unsafe {
+            if self.is_wide() {
+                let words = std::slice::from_raw_parts(self.large.path, STAMP);
+                words[1] as u64 | ((words[2] as u64) << 32)
+            } else {
+                self.small.clock as u64
+            }
+        }
+    }
+
+    #[inline]
+    /// Returns whether the position’s `site` or `clock` was too wide to be held inline.
+    fn is_wide(&self) -> bool {
+        self.is_heap() && unsafe { self.large.flags & WIDE != 0 }
+    }
+
+    #[inline]
+    /// Returns the number of words allocated on the heap, when [`Position::is_heap()`].
+    fn heap_words(&self) -> usize {
+        let stamp = if self.is_wide() { STAMP } else { 0 };
+        stamp + unsafe { self.large.length as usize }
     }
 
     #[inline]
@@ -173,7 +263,8 @@ impl Position {
             if self.is_inline() {
                 &self.small.path[..self.level()]
             } else {
-                std::slice::from_raw_parts(self.large.path, self.large.length as usize)
+                let offset = if self.is_wide() { STAMP } else { 0 };
+                std::slice::from_raw_parts(self.large.path.add(offset), self.large.length as usize)
             }
         }
     }
@@ -3,6 +3,11 @@ use std::ops::Range;
 
 use fastrand::Rng;
 
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_crate::Serialize, serde_crate::Deserialize),
+    serde(crate = "serde_crate")
+)]
 pub(crate) enum Allocator {
     BoundaryPlus(u32),
     BoundaryMinus(u32),
@@ -10,6 +15,7 @@ pub(crate) enum Allocator {
         limit: u32,
         choices: BTreeMap<u32, bool>,
     },
+    NonInterleaving(u32),
 }
 
 impl Allocator {
@@ -18,6 +24,7 @@ impl Allocator {
             Allocator::BoundaryPlus(limit) => *limit as usize,
             Allocator::BoundaryMinus(limit) => *limit as usize,
             Allocator::Boundaries { limit, .. } => *limit as usize,
+            Allocator::NonInterleaving(limit) => *limit as usize,
         }
     }
 
@@ -28,9 +35,9 @@ impl Allocator {
         rng: &mut Rng,
     ) -> Range<u32> {
         match self {
-            Allocator::BoundaryPlus(limit) => Range {
+            Allocator::BoundaryPlus(limit) | Allocator::NonInterleaving(limit) => Range {
                 start: range.start,
-                end: range.end.min(range.start + *limit),
+                end: range.end.min(range.start.saturating_add(*limit)),
             },
             Allocator::BoundaryMinus(limit) => Range {
                 start: (range.end.saturating_sub(*limit)).max(range.start),
@@ -6,7 +6,7 @@ mod allocator;
 
 pub(crate) type Builder = tinyvec::TinyVec<[u32; INLINE]>;
 
-pub(crate) const FIRST: [u32; 1] = [0];
+pub(crate) const FIRST: [u32; 0] = [];
 pub(crate) const LAST: [u32; 3] = [
     Position::end_bound(0),
     Position::end_bound(1),
@@ -14,15 +14,17 @@ pub(crate) const LAST: [u32; 3] = [
 ];
 
 impl Position {
+    /// The sentinel that comes before every element of a document, holding none itself.
     #[inline]
-    pub(crate) fn first() -> Position {
+    pub fn first() -> Position {
         Position {
             small: Default::default(),
         }
     }
 
+    /// The sentinel that comes after every element of a document; inserting before it appends.
     #[inline]
-    pub(crate) fn last() -> Position {
+    pub fn last() -> Position {
         Position {
             small: Small {
                 path: LAST,
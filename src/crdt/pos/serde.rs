@@ -1,61 +1,114 @@
-use serde_crate::{Deserialize, Serialize};
+use serde_crate::{Deserialize, Deserializer, Serialize, Serializer};
 
 use crate::crdt::pos::path::Builder;
-use crate::Position;
+use crate::{InvalidPosition, Position, SiteRegistry, UnknownSite};
 
 #[derive(Default, Serialize, Deserialize)]
 #[serde(crate = "serde_crate")]
-struct Payload {
+pub(crate) struct Payload {
     site: u128, // `site`s are represented by UUIDs externally
-    clock: u16,
+    clock: u64, // was a `u16`; self-describing formats read those as readily
     path: Builder,
 }
 
 impl Payload {
-    pub fn from_position(pos: &Position, uuid: impl FnOnce(u16) -> u128) -> Self {
+    /// Describes `pos`, made by the site identified by `uuid`.
+    pub fn new(pos: &Position, uuid: u128) -> Self {
         Payload {
-            site: uuid(pos.site_id()),
+            site: uuid,
             clock: pos.clock(),
             path: Builder::from(pos.path()),
         }
     }
 
-    pub fn into_position(self, site_id: impl FnOnce(u128) -> u16) -> Position {
-        Position::new(site_id(self.site), self.clock, &self.path)
+    pub fn try_from_position(pos: &Position, sites: &SiteRegistry) -> Result<Self, UnknownSite> {
+        Ok(Payload::new(pos, sites.try_uuid(pos.site_id())?))
+    }
+
+    /// Returns the UUID of the site that created the position.
+    pub fn uuid(&self) -> u128 {
+        self.site
+    }
+
+    /// Validates the payload, as it may have come from anywhere, before registering its site.
+    pub fn try_into_position(self, sites: &mut SiteRegistry) -> Result<Position, InvalidPosition> {
+        // a UUID that isn’t registered is never the nil one, of the sentinels
+        let site = sites.site_id(self.site).unwrap_or(u32::MAX);
+        Position::validate(site, self.clock, &self.path)?;
+        Ok(Position::new(
+            sites.register(self.site),
+            self.clock,
+            &self.path,
+        ))
+    }
+}
+
+impl Serialize for SiteRegistry {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+impl<'de> Deserialize<'de> for SiteRegistry {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let claims = Vec::<(u32, u128)>::deserialize(deserializer)?;
+        Ok(SiteRegistry::from_claims(claims))
     }
 }
 
 #[test]
-#[ignore]
-fn notes_quick_uuid() {
-    // A standard conforming implementation of UUIDs in three lines.
-    let mut uuid = fastrand::u128(..).to_be_bytes();
-    uuid[6] = (uuid[6] & 0x0F) | 0x40; // Version 4
-    uuid[8] = (uuid[8] & 0x3F) | 0x80; // Variant 1
-
-    let uuid = u128::from_be_bytes(uuid);
-    println!("{:x}", uuid);
+fn payload_round_trip() {
+    let mut a = SiteRegistry::default();
+    let mut b = SiteRegistry::default();
+
+    let uuid = crate::crdt::sites::uuid();
+    let pos = Position::new(a.register(uuid), 7, &[1, 2, 3, 4]);
+
+    // `b` learns about `uuid` from the payload itself
+    let payload = Payload::try_from_position(&pos, &a).unwrap();
+    let copy = payload.try_into_position(&mut b).unwrap();
+
+    assert_eq!(copy.path(), pos.path());
+    assert_eq!(b.uuid(copy.site_id()), Some(uuid));
+
+    let payload = Payload::try_from_position(&Position::first(), &a).unwrap();
+    assert_eq!(payload.try_into_position(&mut b), Ok(Position::first()));
+
+    // nor can a site `a` doesn’t know of be translated
+    let site = (1..).find(|site| a.uuid(*site).is_none()).unwrap();
+    let pos = Position::new(site, 7, &[1]);
+    assert_eq!(
+        Payload::try_from_position(&pos, &a).err(),
+        Some(UnknownSite(site))
+    );
 }
 
 #[test]
-#[ignore]
-fn notes_quick_site_id_lookup() {
-    // As funny/functional as this is, it doesn’t remove
-    // the need for a reverse map from uuid → site id.
-    // Also, it makes for very predicable uuids…
-    let uuid_for_site_id = |site: u16| -> u128 {
-        if site == 0 {
-            return 0;
-        }
+fn narrow_clocks_still_load() {
+    // a payload saved while the `clock` was only 16 bits wide
+    let json = r#"{"site":0,"clock":65535,"path":[2,3]}"#;
+    let payload: Payload = serde_json::from_str(json).unwrap();
+
+    let pos = payload
+        .try_into_position(&mut SiteRegistry::default())
+        .unwrap();
+    assert_eq!(pos.clock(), u16::MAX as u64);
+    assert_eq!(pos.path(), &[2, 3]);
+}
 
-        let mut rng = fastrand::Rng::with_seed(site as u64);
-        rng.u128(..)
-    };
+#[test]
+fn untrusted_payloads() {
+    let mut sites = SiteRegistry::default();
+    let claims = sites.iter().count();
 
-    let a = uuid_for_site_id(1);
-    let b = uuid_for_site_id(1);
-    assert_eq!(a, b);
+    // level zero overlaps the tag of a heap `Position`
+    let json = r#"{"site":7,"clock":1,"path":[4294967295]}"#;
+    let payload: Payload = serde_json::from_str(json).unwrap();
+    assert_eq!(
+        payload.try_into_position(&mut sites),
+        Err(InvalidPosition::OutOfBounds(u32::MAX))
+    );
 
-    let c = uuid_for_site_id(2);
-    assert_ne!(a, c);
+    // the site isn’t registered for a payload that is turned away
+    assert_eq!(sites.iter().count(), claims);
 }
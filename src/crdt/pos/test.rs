@@ -1,5 +1,6 @@
 use std::mem::size_of;
 
+use quickcheck::TestResult;
 use quickcheck_macros::quickcheck;
 
 use super::*;
@@ -44,13 +45,84 @@ fn layout() {
 }
 
 #[quickcheck]
-fn property_testing(site: u16, clock: u16, nums: Vec<std::num::NonZeroU32>) {
+fn property_testing(site: u32, clock: u64, nums: Vec<std::num::NonZeroU32>) -> TestResult {
     let nums: Vec<_> = nums.iter().map(|n| n.get()).collect();
 
+    // see `layout()`; such a level zero is never generated
+    if nums.len() <= INLINE && nums.first() >= Some(&Position::end_bound(0)) {
+        return TestResult::discard();
+    }
+
     let position = Position::new(site, clock, &nums);
 
     // small positions will be zero-padded; remove them before we compare
     let result = &position.path();
 
     assert_eq!(&nums, result);
+    assert_eq!(position.clock(), clock);
+    assert_eq!(position.site_id(), site);
+    assert_eq!(position.clone(), position);
+    TestResult::passed()
+}
+
+#[test]
+fn wide_stamps() {
+    let clock = u16::MAX as u64 + 1;
+
+    // a wide `clock` spills onto the heap along with the path, however short
+    let position = Position::new(1, clock, &[2]);
+    assert!(position.is_heap());
+    assert_eq!(position.clock(), clock);
+    assert_eq!(position.path(), &[2]);
+
+    let position = Position::new(1, u64::MAX, &[2, 3, 4, 5]);
+    assert_eq!(position.clone().clock(), u64::MAX);
+    assert_eq!(position.path(), &[2, 3, 4, 5]);
+
+    // while a narrow one is still held inline
+    assert!(Position::new(1, u16::MAX as u64, &[2]).is_inline());
+    assert!(Position::new(1, clock - 1, &[2]) < Position::new(1, clock, &[2]));
+
+    // as are wide sites
+    let site = u16::MAX as u32 + 1;
+    let position = Position::new(site, 1, &[2]);
+    assert!(position.is_heap());
+    assert_eq!(position.site_id(), site);
+    assert_eq!(position.clock(), 1);
+    assert!(Position::new(site - 1, 1, &[2]) < position);
+}
+
+#[test]
+fn untrusted_paths() {
+    let level = Position::end_bound(0);
+
+    // `layout()` can't happen
+    assert_eq!(
+        Position::try_new(0, 0, &[0xffffffff]),
+        Err(InvalidPosition::OutOfBounds(0xffffffff))
+    );
+    assert_eq!(
+        Position::try_new(0, 0, &[level]),
+        Err(InvalidPosition::Reserved)
+    );
+    assert_eq!(
+        Position::try_new(0, 0, &[1, 0, 2]),
+        Err(InvalidPosition::Zero(1))
+    );
+    assert_eq!(
+        Position::try_new(0, 0, &vec![1; 1 << 16]),
+        Err(InvalidPosition::TooLong(1 << 16))
+    );
+
+    // …while the sentinels and anything generated are fine
+    assert_eq!(Position::try_new(0, 0, &[]), Ok(Position::first()));
+    assert_eq!(Position::try_new(0, 0, &path::LAST), Ok(Position::last()));
+
+    // but only with their own site and clock, which would otherwise be ordered among the elements
+    assert_eq!(Position::try_new(1, 0, &[]), Err(InvalidPosition::Sentinel));
+    assert_eq!(
+        Position::try_new(0, 7, &path::LAST),
+        Err(InvalidPosition::Sentinel)
+    );
+    assert!(Position::try_new(0, 0, &[level - 1, u32::MAX, 1, 2]).is_ok());
 }
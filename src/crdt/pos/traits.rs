@@ -1,7 +1,8 @@
 use std::cmp::Ordering;
-use std::fmt::{Debug, Formatter};
+use std::fmt::{Debug, Display, Formatter};
 use std::hash::{Hash, Hasher};
 
+use crate::crdt::pos::InvalidPosition;
 use crate::Position;
 
 impl PartialOrd for Position {
@@ -47,3 +48,19 @@ impl Debug for Position {
             .finish()
     }
 }
+
+impl Display for InvalidPosition {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InvalidPosition::Reserved => write!(f, "level zero is reserved for the last position"),
+            InvalidPosition::OutOfBounds(n) => write!(f, "level zero is out of bounds: {n:#x}"),
+            InvalidPosition::Zero(level) => write!(f, "level {level} is zero"),
+            InvalidPosition::TooLong(len) => write!(f, "path is too long: {len} levels"),
+            InvalidPosition::Sentinel => {
+                write!(f, "only the first and last positions have their paths")
+            }
+        }
+    }
+}
+
+impl std::error::Error for InvalidPosition {}
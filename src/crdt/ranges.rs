@@ -1,25 +1,25 @@
-use std::{ops::Bound::Unbounded, ops::RangeBounds};
+use std::ops::RangeBounds;
 
 use itertools::Itertools;
 use unicode_segmentation::UnicodeSegmentation;
 
-use crate::{Position, Storage};
+use crate::{Element, Position, Storage};
+
+impl<T: Element> Storage<T> {
+    pub fn elements(
+        &self,
+        range: impl RangeBounds<Position>,
+    ) -> impl DoubleEndedIterator<Item = (&Position, &T)> {
+        self.elements.range(range)
+    }
+}
 
 impl Storage {
     pub fn characters(
         &self,
         range: impl RangeBounds<Position>,
-    ) -> impl Iterator<Item = (&Position, &char)> {
-        // skip `Position::first()` as is it an `Exclusive` bound
-        let skip = (range.start_bound() == Unbounded) as usize;
-
-        // drop `Position::last()` as is it an `Exclusive` bound
-        let drop = (range.end_bound() == Unbounded) as usize;
-
-        self.characters
-            .range(range)
-            .dropping(skip)
-            .dropping_back(drop)
+    ) -> impl DoubleEndedIterator<Item = (&Position, &char)> {
+        self.elements(range)
     }
 
     pub fn string(&self, range: impl RangeBounds<Position>) -> String {
@@ -29,15 +29,15 @@ impl Storage {
     pub fn graphemes<'a>(
         &'a self,
         range: impl RangeBounds<Position> + 'a,
-    ) -> impl Iterator<Item = (&Position, &Position)> + 'a {
-        // skip `Position::first()` as is it an `Exclusive` bound
-        let skip = (range.start_bound() == Unbounded) as usize;
+    ) -> impl Iterator<Item = (&'a Position, &'a Position)> + 'a {
+        // appends `Position::last()` (when in `range`) as is it needed by `tuple_windows()`
+        let last = range.contains(self.last()).then(|| self.last());
 
-        // preserves `Position::last()` as is it needed by `tuple_windows()`
         GraphemeBoundary {
-            iter: self.characters.range(range).dropping(skip),
+            iter: self.characters(range),
             string: Default::default(),
         }
+        .chain(last)
         .tuple_windows()
     }
 
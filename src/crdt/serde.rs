@@ -0,0 +1,527 @@
+use serde_crate::de::{DeserializeOwned, Error};
+use serde_crate::ser::Error as _;
+use serde_crate::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::crdt::deleted::{Deleted, Dots};
+use crate::crdt::pos::path::algorithm::Algorithm;
+use crate::crdt::pos::serde::Payload;
+use crate::crdt::sites::Renames;
+use crate::{
+    Anchor, Awareness, Claims, Cursor, Element, Gravity, Mark, Marks, Position, Presence,
+    Selection, SiteRegistry, Storage, UnknownSite, VersionVector,
+};
+
+/// A borrowed view of a [`Storage`], ready for serialization.
+///
+/// The `newlines` and order-statistic indices are rebuilt on load, as both are
+/// derived from the `elements`. The `deleted` positions are saved as they are kept:
+/// as ranges of the last level of a path, each with the edits that deleted it.
+/// The `algorithm` is saved with its
+/// per-level LSEQ `choices` and random seed so that a restored replica keeps
+/// generating positions exactly as it would have before.
+#[derive(Serialize)]
+#[serde(crate = "serde_crate")]
+struct Snapshot<'a, T> {
+    uuid: u128,
+    site: u32,
+    clock: u64,
+    sites: &'a SiteRegistry,
+    algorithm: &'a Algorithm,
+    version: &'a VersionVector,
+    elements: Vec<(Payload, &'a T)>,
+    deleted: Vec<(Payload, u32, &'a Dots)>, // the first position of each range, and its end
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "serde_crate")]
+struct Restored<T> {
+    uuid: u128,
+    site: u32,
+    clock: u64,
+    sites: Vec<(u32, u128)>,
+    algorithm: Algorithm,
+    version: VersionVector,
+    #[serde(bound = "T: DeserializeOwned")]
+    elements: Vec<(Payload, T)>,
+    #[serde(default)]
+    deleted: Vec<(Payload, u32, Dots)>,
+}
+
+impl<T: Element + Serialize> Serialize for Storage<T> {
+    /// Every position is saved with the UUID of its site; which is registered however it got into
+    /// the document, be it by a merge or an [applied](Storage::apply) operation.
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let payload =
+            |pos: &Position| Payload::try_from_position(pos, &self.sites).map_err(S::Error::custom);
+
+        Snapshot {
+            uuid: self.uuid,
+            site: self.site,
+            clock: self.clock,
+            sites: &self.sites,
+            algorithm: &self.algorithm,
+            elements: self
+                .elements(..)
+                .map(|(pos, value)| Ok((payload(pos)?, value)))
+                .collect::<Result<_, _>>()?,
+            version: &self.version,
+            deleted: self
+                .deleted
+                .runs()
+                .map(|(run, values, dots)| {
+                    let start = Deleted::join(run, *values.start());
+                    Ok((payload(&start)?, *values.end(), dots))
+                })
+                .collect::<Result<_, _>>()?,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de, T: Element + DeserializeOwned> Deserialize<'de> for Storage<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let restored = Restored::<T>::deserialize(deserializer)?;
+
+        // every site a position mentions is registered up front, so that none move while loading
+        let mut sites = SiteRegistry::from_claims(restored.sites.iter().copied());
+        sites.extend(
+            restored
+                .elements
+                .iter()
+                .map(|(payload, _)| payload)
+                .chain(restored.deleted.iter().map(|(payload, ..)| payload))
+                .map(Payload::uuid)
+                .chain([restored.uuid]),
+        );
+
+        // the ids the snapshot was saved with, should any differ from those they have now
+        let mut ids = Renames::new();
+        for (site, uuid) in &restored.sites {
+            ids.entry(*site)
+                .or_insert(sites.site_id(*uuid).unwrap_or(*site));
+        }
+        ids.retain(|old, new| old != new);
+
+        let mut storage = Storage {
+            algorithm: restored.algorithm,
+            clock: restored.clock,
+            site: sites.site_id(restored.uuid).unwrap_or(restored.site),
+            uuid: restored.uuid,
+            version: restored.version.renamed(&ids),
+            ..Default::default()
+        };
+
+        let mut position = |payload: Payload| {
+            payload
+                .try_into_position(&mut sites)
+                .map_err(D::Error::custom)
+        };
+
+        // deletions first, so that no element they remove is ever indexed
+        for (payload, end, dots) in restored.deleted {
+            let pos = position(payload)?;
+            let (run, start) =
+                Deleted::split(&pos).ok_or_else(|| D::Error::custom("empty path"))?;
+
+            // the end of the range must be as valid as its start
+            let mut path = pos.path().to_vec();
+            path.pop();
+            path.push(end);
+            Position::validate(pos.site_id(), pos.clock(), &path).map_err(D::Error::custom)?;
+
+            let dots = dots.iter().map(|dot| dot.renamed(&ids)).collect();
+            if !storage.deleted.insert_range(run, start..=end, dots) {
+                return Err(D::Error::custom("overlapping or empty deletions"));
+            }
+        }
+
+        for (payload, value) in restored.elements {
+            let _ = storage.insert_element(position(payload)?, value);
+        }
+
+        storage.sites = sites;
+        Ok(storage)
+    }
+}
+
+/// The presence of a site, with it and the positions of its cursors identified by UUIDs.
+#[derive(Serialize)]
+#[serde(crate = "serde_crate")]
+struct Peer<'a, M> {
+    site: u128,
+    clock: u64,
+    heartbeat: u64,
+    metadata: &'a M,
+    selections: Vec<[(Payload, Gravity); 2]>,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "serde_crate")]
+struct RestoredPeer<M> {
+    site: u128,
+    clock: u64,
+    heartbeat: u64,
+    metadata: M,
+    selections: Vec<[(Payload, Gravity); 2]>,
+}
+
+impl<M: Serialize> Serialize for Awareness<M> {
+    /// The presence of every site, identified — as are the sites of the positions of its cursors —
+    /// by UUID.
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let cursor = |cursor: &Cursor| (Payload::new(&cursor.pos, cursor.uuid), cursor.gravity);
+
+        serializer.collect_seq(self.iter().map(|(uuid, presence)| {
+            Peer {
+                site: uuid,
+                clock: presence.clock,
+                heartbeat: presence.heartbeat,
+                metadata: &presence.metadata,
+                selections: presence
+                    .selections
+                    .iter()
+                    .map(|selection| [cursor(&selection.anchor), cursor(&selection.head)])
+                    .collect(),
+            }
+        }))
+    }
+}
+
+impl<M: DeserializeOwned> Awareness<M> {
+    /// Deserializes the presence of every site, registering any of them not yet known with the
+    /// document in `storage`.
+    pub fn deserialize_with<'de, D: Deserializer<'de>, T: Element>(
+        deserializer: D,
+        storage: &mut Storage<T>,
+    ) -> Result<Self, D::Error> {
+        let peers = Vec::<RestoredPeer<M>>::deserialize(deserializer)?;
+
+        let cursors = peers
+            .iter()
+            .flat_map(|peer| peer.selections.iter().flatten());
+        let uuids = cursors.map(|(payload, _)| payload.uuid());
+        storage.register(
+            peers
+                .iter()
+                .map(|peer| peer.site)
+                .chain(uuids)
+                .collect::<Vec<_>>(),
+        );
+
+        let sites = &mut storage.sites;
+        let mut awareness = Awareness::default();
+        for peer in peers {
+            let mut cursor = |(payload, gravity): (Payload, Gravity)| {
+                Ok(Cursor {
+                    uuid: payload.uuid(),
+                    pos: payload.try_into_position(sites).map_err(D::Error::custom)?,
+                    gravity,
+                })
+            };
+
+            let selections = peer
+                .selections
+                .into_iter()
+                .map(|[anchor, head]| {
+                    Ok(Selection {
+                        anchor: cursor(anchor)?,
+                        head: cursor(head)?,
+                    })
+                })
+                .collect::<Result<_, D::Error>>()?;
+
+            let presence = Presence {
+                selections,
+                metadata: peer.metadata,
+                heartbeat: peer.heartbeat,
+                clock: peer.clock,
+            };
+
+            awareness.peers.insert(peer.site, presence);
+        }
+
+        Ok(awareness)
+    }
+}
+
+/// An [`Anchor`], with the site of its position identified by UUID.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "serde_crate")]
+enum SavedAnchor {
+    Before(Payload),
+    After(Payload),
+}
+
+impl SavedAnchor {
+    /// Describes `anchor`, given the UUIDs of the `sites` of the mark it belongs to.
+    fn try_from_anchor(anchor: &Anchor, sites: &Claims) -> Result<Self, UnknownSite> {
+        let payload = |pos: &Position| {
+            let uuid = sites
+                .uuid(pos.site_id())
+                .ok_or(UnknownSite(pos.site_id()))?;
+            Ok(Payload::new(pos, uuid))
+        };
+
+        Ok(match anchor {
+            Anchor::Before(pos) => SavedAnchor::Before(payload(pos)?),
+            Anchor::After(pos) => SavedAnchor::After(payload(pos)?),
+        })
+    }
+
+    /// Returns the UUID of the site that created the position of the anchor.
+    fn uuid(&self) -> u128 {
+        match self {
+            SavedAnchor::Before(payload) | SavedAnchor::After(payload) => payload.uuid(),
+        }
+    }
+
+    /// Validates the anchor, as it may have come from anywhere, before registering its site.
+    fn try_into_anchor<E: Error>(self, sites: &mut SiteRegistry) -> Result<Anchor, E> {
+        Ok(match self {
+            SavedAnchor::Before(payload) => {
+                Anchor::Before(payload.try_into_position(sites).map_err(E::custom)?)
+            }
+            SavedAnchor::After(payload) => {
+                Anchor::After(payload.try_into_position(sites).map_err(E::custom)?)
+            }
+        })
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "serde_crate")]
+struct SavedMark<'a, V> {
+    clock: u64,
+    uuid: u128,
+    start: SavedAnchor,
+    end: SavedAnchor,
+    name: &'a str,
+    value: &'a Option<V>,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "serde_crate")]
+struct RestoredMark<V> {
+    clock: u64,
+    uuid: u128,
+    start: SavedAnchor,
+    end: SavedAnchor,
+    name: String,
+    value: Option<V>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "serde_crate")]
+struct SavedMarks<'a, V> {
+    clock: u64,
+    marks: Vec<SavedMark<'a, V>>,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "serde_crate")]
+struct RestoredMarks<V> {
+    clock: u64,
+    marks: Vec<RestoredMark<V>>,
+}
+
+impl<V: Serialize> Serialize for Marks<V> {
+    /// Every mark, with the sites of its anchors identified by UUID.
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let marks = self.marks.iter().map(|mark| {
+            Ok(SavedMark {
+                clock: mark.clock,
+                uuid: mark.uuid,
+                start: SavedAnchor::try_from_anchor(&mark.start, &mark.sites)?,
+                end: SavedAnchor::try_from_anchor(&mark.end, &mark.sites)?,
+                name: &mark.name,
+                value: &mark.value,
+            })
+        });
+
+        SavedMarks {
+            clock: self.clock,
+            marks: marks
+                .collect::<Result<_, UnknownSite>>()
+                .map_err(S::Error::custom)?,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de, V: DeserializeOwned> Deserialize<'de> for Marks<V> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let saved = RestoredMarks::<V>::deserialize(deserializer)?;
+
+        // the ids given to the sites here need only agree with the `sites` of each mark; so long
+        // as none is moved, once given, by registering another
+        let mut sites = SiteRegistry::default();
+        let anchors = saved.marks.iter().flat_map(|mark| [&mark.start, &mark.end]);
+        sites.extend(anchors.map(SavedAnchor::uuid));
+
+        let mut marks = Vec::with_capacity(saved.marks.len());
+        for mark in saved.marks {
+            let start = mark.start.try_into_anchor(&mut sites)?;
+            let end = mark.end.try_into_anchor(&mut sites)?;
+            let anchors = [&start, &end].map(|anchor| match anchor {
+                Anchor::Before(pos) | Anchor::After(pos) => pos.site_id(),
+            });
+
+            marks.push(Mark {
+                clock: mark.clock,
+                uuid: mark.uuid,
+                sites: sites.claims(anchors),
+                start,
+                end,
+                name: mark.name,
+                value: mark.value,
+            });
+        }
+
+        // in the order they take effect, as they may have come from anywhere
+        marks.sort_by_key(|mark| (mark.clock, mark.uuid));
+        marks.dedup_by_key(|mark| (mark.clock, mark.uuid));
+
+        let clock = marks
+            .iter()
+            .map(|mark| mark.clock)
+            .max()
+            .unwrap_or_default();
+        Ok(Marks {
+            marks,
+            clock: Ord::max(saved.clock, clock),
+        })
+    }
+}
+
+#[test]
+fn snapshot_round_trip() {
+    let mut storage = Storage::with_strategy(crate::Strategy::Boundaries(16));
+    storage.extend("hello,\nworld".chars());
+
+    let pos = storage.characters(..).nth(5).map(|(pos, _)| pos.clone());
+    let _ = storage.remove(&pos.unwrap());
+
+    let json = serde_json::to_string(&storage).unwrap();
+    let mut restored: Storage = serde_json::from_str(&json).unwrap();
+
+    assert_eq!(restored.string(..), storage.string(..));
+    assert_eq!(restored.lines(..).count(), storage.lines(..).count());
+    assert_eq!(restored.deleted, storage.deleted);
+    assert_eq!(restored.version, storage.version);
+    assert_eq!(restored.sites, storage.sites);
+
+    // the restored replica makes the same choices as the original
+    assert_eq!(
+        restored.append("!".repeat(50).chars()),
+        storage.append("!".repeat(50).chars())
+    );
+}
+
+#[test]
+fn untrusted_snapshots() {
+    let mut storage = Storage::default();
+    storage.extend("hi".chars());
+
+    let json = serde_json::to_string(&storage).unwrap();
+    let json = json.replacen(r#""path":["#, r#""path":[4294967295,"#, 1);
+
+    let error = serde_json::from_str::<Storage>(&json).err().unwrap();
+    assert!(error.to_string().contains("out of bounds"));
+}
+
+#[test]
+fn applied_snapshots() {
+    let mut a = Storage::with_uuid(1);
+    let mut b = Storage::with_uuid(2);
+    for op in a.append("hello".chars()) {
+        b.apply(&op);
+    }
+
+    // b learnt the UUID of a’s site from its operations, so can save its positions
+    let json = serde_json::to_string(&b).unwrap();
+    let restored: Storage = serde_json::from_str(&json).unwrap();
+    assert_eq!(restored.string(..), "hello");
+    assert_eq!(restored.sites(), b.sites());
+}
+
+#[test]
+fn awareness_round_trip() {
+    use crate::Merge;
+
+    let mut a = Storage::with_uuid(1 + u16::MAX as u128);
+    let mut b = Storage::with_uuid(1); // which would claim the same site id
+    for op in a.append("hello".chars()) {
+        b.apply(&op);
+    }
+
+    let mut presence = Awareness::default();
+    let selection = Selection::at(&a, 1, 4).unwrap();
+    presence.update(a.uuid(), vec![selection.clone()], "alice".to_string(), 100);
+    let json = serde_json::to_vec(&presence).unwrap();
+
+    // b knows a by another site id, yet the selection resolves all the same
+    let mut deserializer = serde_json::Deserializer::from_slice(&json);
+    let restored = Awareness::<String>::deserialize_with(&mut deserializer, &mut b).unwrap();
+
+    let mut other = Awareness::default();
+    other.merge(&restored);
+
+    assert_ne!(b.sites().site_id(a.uuid()), a.sites().site_id(a.uuid()));
+    let peer = other.get(a.uuid()).unwrap();
+    assert_eq!(peer.metadata, "alice");
+    assert_eq!(peer.selections[0].range(&b), selection.range(&a));
+}
+
+#[test]
+fn marks_round_trip() {
+    use crate::Expand;
+
+    let mut a = Storage::with_uuid(1 + u16::MAX as u128);
+    let mut b = Storage::with_uuid(1); // which would claim the same site id
+    for op in a.append("hello world".chars()) {
+        b.apply(&op);
+    }
+
+    let mut marks = Marks::default();
+    let _ = marks.add(&a, 0..5, "bold", true, Expand::Right);
+    let _ = marks.remove(&a, 1..3, "bold", Expand::Right);
+    let json = serde_json::to_string(&marks).unwrap();
+
+    // b knows a by another site id, yet the marks resolve all the same
+    let restored: Marks<bool> = serde_json::from_str(&json).unwrap();
+    assert_ne!(b.sites().site_id(a.uuid()), a.sites().site_id(a.uuid()));
+    assert_eq!(restored.spans(&b), marks.spans(&a));
+    assert_eq!(restored.clock, marks.clock);
+
+    // an anchor is validated, as a position of a snapshot is
+    let json = json.replacen(r#""path":["#, r#""path":[4294967295,"#, 1);
+    assert!(serde_json::from_str::<Marks<bool>>(&json).is_err());
+}
+
+#[test]
+fn compacted_deletions() {
+    let mut storage = Storage {
+        algorithm: Algorithm::with_strategy(crate::Strategy::Boundary),
+        ..Storage::with_uuid(1) // small enough for a `serde_json::Value`
+    };
+    storage.append("hello, world".chars());
+    storage.remove_range(3..10);
+
+    // seven characters, of a single run removed by a single edit, are saved as one range
+    let json = serde_json::to_string(&storage).unwrap();
+    let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(value["deleted"].as_array().unwrap().len(), 1);
+
+    let restored: Storage = serde_json::from_str(&json).unwrap();
+    assert_eq!(restored.deleted, storage.deleted);
+    assert_eq!(
+        restored.operations_since(&VersionVector::default()),
+        storage.operations_since(&VersionVector::default())
+    );
+
+    // a range that runs out of bounds is turned away
+    let mut value = value;
+    value["deleted"][0][1] = u32::MAX.into();
+    let error = serde_json::from_value::<Storage>(value).err().unwrap();
+    assert!(error.to_string().contains("out of bounds"));
+}
@@ -0,0 +1,393 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::ops::RangeInclusive;
+
+use tinyvec::TinyVec;
+
+use crate::{Dot, Element, Merge, Position, Storage};
+
+/// The largest site id that is held inline.
+const NARROW: u32 = u16::MAX as u32;
+
+/// The largest site id; leaving room, at level zero, for [`Strategy::NonInterleaving`]’s tag of `site + 1`.
+///
+/// [`Strategy::NonInterleaving`]: crate::Strategy::NonInterleaving
+const WIDE: u32 = Position::end_bound(0) - 2;
+
+/// Maps the 128-bit UUIDs that identify replicas externally to the compact
+/// site ids stored within each [`Position`].
+///
+/// Site ids are 32 bits wide, but only those that fit in 16 bits are held inline;
+/// so they are handed out first. Once all 65 535 of those are claimed, a document
+/// edited by yet more replicas carries on with wider ids, at the cost of a heap
+/// allocation for each of their positions.
+///
+/// The registry is replicated along with the document, as a grow-only set of UUIDs;
+/// and the ids are derived from that set alone. Taking each UUID in turn, smallest
+/// first, it is given the first unclaimed id of a search that starts from a slot
+/// derived from the UUID itself. So replicas that know of the same sites agree on
+/// every id — which is what lets a [`Position`] mean the same thing everywhere.
+///
+/// Two UUIDs that start their search from the same slot are detected as soon as
+/// a replica learns of both — by a merge, from the wire, or from the [`Claims`] of an
+/// [`Operation`](crate::Operation) it applies: the smaller keeps the id, and the other
+/// moves on to the next. The document then renumbers the positions of the site that
+/// moved, as does every other replica, in the same way, once it learns of them too.
+///
+/// Site id `0` always belongs to the nil UUID; it is reserved for the
+/// [`Position::first()`] and [`Position::last()`] sentinels.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SiteRegistry {
+    sites: BTreeMap<u32, u128>,
+    uuids: BTreeMap<u128, u32>,
+}
+
+/// A site id that isn’t registered, so can’t be translated to the UUID that identifies it elsewhere.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct UnknownSite(pub u32);
+
+/// The UUIDs of the sites an [`Operation`](crate::Operation), or a [`Mark`](crate::Mark), mentions,
+/// by the ids they have at the replica that made it.
+///
+/// Carried along with the operation, so that a replica that knows those sites by other ids — or
+/// not at all — can register them, and translate the operation to its own.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Claims(TinyVec<[(u32, u128); 2]>);
+
+/// The sites that were given new ids, as another was registered: from their old id to their new one.
+pub(crate) type Renames = BTreeMap<u32, u32>;
+
+impl Default for SiteRegistry {
+    fn default() -> Self {
+        let first = Position::first();
+
+        SiteRegistry {
+            sites: BTreeMap::from([(first.site_id(), 0)]),
+            uuids: BTreeMap::from([(0, first.site_id())]),
+        }
+    }
+}
+
+impl SiteRegistry {
+    /// Returns the UUID of the replica that owns `site`.
+    pub fn uuid(&self, site: u32) -> Option<u128> {
+        self.sites.get(&site).copied()
+    }
+
+    /// Returns the UUID of the replica that owns `site`, as [`SiteRegistry::uuid()`], or why it can’t.
+    pub(crate) fn try_uuid(&self, site: u32) -> Result<u128, UnknownSite> {
+        self.uuid(site).ok_or(UnknownSite(site))
+    }
+
+    /// Returns the site id owned by the replica with the given `uuid`.
+    pub fn site_id(&self, uuid: u128) -> Option<u32> {
+        self.uuids.get(&uuid).copied()
+    }
+
+    /// Returns the site id for `uuid`, registering it if it is not yet known.
+    ///
+    /// Registering a site can move another, that it collides with, to a new id.
+    ///
+    /// # Panics
+    ///
+    /// If every site id has been claimed.
+    pub fn register(&mut self, uuid: u128) -> u32 {
+        self.extend([uuid]);
+        self.uuids[&uuid]
+    }
+
+    /// Iterates over each site id and the UUID that owns it.
+    pub fn iter(&self) -> impl Iterator<Item = (u32, u128)> + '_ {
+        self.sites.iter().map(|(site, uuid)| (*site, *uuid))
+    }
+
+    /// Registers each of the `uuids` not yet known, returning the sites that were moved to make room.
+    pub(crate) fn extend(&mut self, uuids: impl IntoIterator<Item = u128>) -> Renames {
+        let mut known: Vec<u128> = self.uuids.keys().copied().collect();
+        let count = known.len();
+
+        known.extend(
+            uuids
+                .into_iter()
+                .filter(|uuid| !self.uuids.contains_key(uuid)),
+        );
+        if known.len() == count {
+            return Renames::new();
+        }
+
+        known.sort_unstable();
+        known.dedup();
+
+        let previous = std::mem::replace(self, Self::from_uuids(known));
+        previous
+            .iter()
+            .map(|(site, uuid)| (site, self.uuids[&uuid]))
+            .filter(|(old, new)| old != new)
+            .collect()
+    }
+
+    /// Returns the UUIDs of those of the `sites` that are registered.
+    pub(crate) fn claims(&self, sites: impl IntoIterator<Item = u32>) -> Claims {
+        sites
+            .into_iter()
+            .filter_map(|site| Some((site, self.uuid(site)?)))
+            .collect()
+    }
+
+    pub(crate) fn from_claims(claims: impl IntoIterator<Item = (u32, u128)>) -> Self {
+        let mut uuids: Vec<u128> = claims.into_iter().map(|(_, uuid)| uuid).collect();
+        uuids.sort_unstable();
+        uuids.dedup();
+
+        Self::from_uuids(uuids)
+    }
+
+    /// Gives each of the `uuids`, in ascending order, the first id its search finds unclaimed.
+    fn from_uuids(uuids: Vec<u128>) -> Self {
+        let mut new = Self::default();
+        for uuid in uuids.into_iter().filter(|uuid| *uuid != 0) {
+            let site = Self::probe(uuid, 1..=NARROW)
+                .chain(Self::probe(uuid, NARROW + 1..=WIDE))
+                .find(|site| !new.sites.contains_key(site))
+                .expect("all site ids have been claimed");
+
+            new.sites.insert(site, uuid);
+            new.uuids.insert(uuid, site);
+        }
+
+        new
+    }
+
+    /// Visits every id in `range`, starting from a slot derived from the `uuid`.
+    fn probe(uuid: u128, range: RangeInclusive<u32>) -> impl Iterator<Item = u32> {
+        let (first, len) = (
+            *range.start() as u128,
+            (range.end() - range.start()) as u128 + 1,
+        );
+        let start = uuid % len;
+
+        (0..len).map(move |n| (first + (start + n) % len) as u32)
+    }
+}
+
+impl SiteRegistry {
+    /// Returns `pos`, from a replica whose sites are registered in `from`, with the id it has here.
+    ///
+    /// As for a [`Merge`] of documents, a site that `from` doesn’t know is taken to have the same id.
+    pub(crate) fn translate(&self, pos: &Position, from: &SiteRegistry) -> Position {
+        let site = from.uuid(pos.site_id()).and_then(|uuid| self.site_id(uuid));
+        match site {
+            Some(site) if site != pos.site_id() => Position::new(site, pos.clock(), pos.path()),
+            _ => pos.clone(),
+        }
+    }
+}
+
+impl Claims {
+    /// Returns the UUID claimed for `site`; site `0` always belongs to the nil UUID.
+    pub fn uuid(&self, site: u32) -> Option<u128> {
+        match site {
+            0 => Some(0),
+            _ => self
+                .iter()
+                .find(|(id, _)| *id == site)
+                .map(|(_, uuid)| uuid),
+        }
+    }
+
+    /// Iterates over each site id and the UUID claimed for it.
+    pub fn iter(&self) -> impl Iterator<Item = (u32, u128)> + '_ {
+        self.0.iter().copied()
+    }
+}
+
+impl FromIterator<(u32, u128)> for Claims {
+    fn from_iter<I: IntoIterator<Item = (u32, u128)>>(iter: I) -> Self {
+        let mut claims = Claims::default();
+        for (site, uuid) in iter {
+            if claims.uuid(site).is_none() {
+                claims.0.push((site, uuid));
+            }
+        }
+
+        claims
+    }
+}
+
+impl Merge for SiteRegistry {
+    /// Every site registered with either.
+    fn merge(&mut self, other: &Self) {
+        self.extend(other.uuids.keys().copied());
+    }
+}
+
+impl Position {
+    /// Returns this position, with its site renumbered should it be one of the `renames`.
+    pub(crate) fn renamed(&self, renames: &Renames) -> Position {
+        match renames.get(&self.site_id()) {
+            Some(site) => Position::new(*site, self.clock(), self.path()),
+            None => self.clone(),
+        }
+    }
+}
+
+impl Dot {
+    /// Returns this dot, with its site renumbered should it be one of the `renames`.
+    pub(crate) fn renamed(&self, renames: &Renames) -> Dot {
+        Dot {
+            site: renames.get(&self.site).copied().unwrap_or(self.site),
+            clock: self.clock,
+        }
+    }
+}
+
+impl Display for UnknownSite {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "site {} isn't registered", self.0)
+    }
+}
+
+impl std::error::Error for UnknownSite {}
+
+impl<T: Element> Storage<T> {
+    /// Creates a replica for the site identified by `uuid`, that knows of the same `sites` as
+    /// another; so that the ids of both agree.
+    pub(crate) fn with_sites(sites: &SiteRegistry, uuid: u128) -> Self {
+        let mut storage = Storage::with_uuid(uuid);
+        storage.register(sites.iter().map(|(_, uuid)| uuid));
+        storage
+    }
+
+    /// Returns `pos`, made by the site identified by `uuid`, with the id that site has here; or as
+    /// it is, should the site not be registered.
+    pub(crate) fn local(&self, pos: &Position, uuid: u128) -> Position {
+        match self.sites.site_id(uuid) {
+            Some(site) if site != pos.site_id() => Position::new(site, pos.clock(), pos.path()),
+            _ => pos.clone(),
+        }
+    }
+
+    /// Registers the sites of the `uuids` not yet known, renumbering the positions of those moved
+    /// to make room; and returning their new ids.
+    pub(crate) fn register(&mut self, uuids: impl IntoIterator<Item = u128>) -> Renames {
+        let renames = self.sites.extend(uuids);
+        if renames.is_empty() {
+            return renames;
+        }
+
+        self.site = self.sites.site_id(self.uuid).unwrap(); // SAFETY: its own site is never forgotten
+        if let Some(Some(dot)) = &mut self.transaction {
+            *dot = dot.renamed(&renames);
+        }
+
+        self.version = self.version.renamed(&renames);
+        self.deleted = self.deleted.renamed(&renames);
+
+        // a renumbered site may now sort differently among the positions that share its path
+        let elements = std::mem::take(&mut self.elements);
+        self.newlines
+            .retain(|pos| *pos == Position::first() || *pos == Position::last());
+        self.index = Default::default();
+        for (pos, value) in elements {
+            let _ = self.insert_element(pos.renamed(&renames), value);
+        }
+
+        renames
+    }
+}
+
+/// Generates a random (version 4) UUID.
+pub(crate) fn uuid() -> u128 {
+    let mut uuid = fastrand::u128(..).to_be_bytes();
+    uuid[6] = (uuid[6] & 0x0F) | 0x40; // Version 4
+    uuid[8] = (uuid[8] & 0x3F) | 0x80; // Variant 1
+
+    u128::from_be_bytes(uuid)
+}
+
+#[test]
+fn concurrent_claims() {
+    let mut a = SiteRegistry::default();
+    let mut b = SiteRegistry::default();
+
+    // two UUIDs that start their search from the same slot
+    let x = 1;
+    let y = 1 + u16::MAX as u128;
+
+    assert_eq!(a.register(x), b.register(y));
+    assert_eq!(a.register(x), a.register(x));
+
+    let site = a.site_id(x).unwrap();
+    b.merge(&a);
+    a.merge(&b);
+    assert_eq!(a, b);
+
+    // the smaller UUID keeps the id, the other moves on to the next
+    assert_eq!(a.uuid(site), Some(x));
+    assert_eq!(b.site_id(y), Some(site + 1));
+    assert_eq!(b.register(y), site + 1);
+
+    assert_eq!(a.uuid(0), Some(0));
+    assert_eq!(b.iter().count(), 3);
+
+    // as do the documents of the replicas, which converge once they learn of one another
+    let mut a = Storage::with_uuid(x);
+    let mut b = Storage::with_uuid(y);
+    assert_eq!(a.site, b.site);
+
+    a.append("xx".chars());
+    b.append("yy".chars());
+    let ops = a.append("zz".chars());
+
+    let mut c = Storage::with_uuid(3);
+    let bytes = crate::wire::encode(&ops).unwrap();
+    crate::wire::decode(&bytes, &mut c).unwrap();
+
+    b.merge(&a);
+    a.merge(&b);
+    c.merge(&b);
+    assert_ne!(a.site, b.site);
+    assert_eq!(a.string(..), b.string(..));
+    assert_eq!(a.string(..), c.string(..));
+    assert_eq!(a.string(..).len(), 6);
+    assert_eq!(a.sites(), b.sites());
+}
+
+#[test]
+fn wide_site_ids() {
+    use crate::Storage;
+
+    // every id that can be held inline is claimed…
+    let claims = (1..=NARROW).map(|site| (site, site as u128));
+    let mut sites = SiteRegistry::from_claims(claims);
+
+    // …so the next replica is given a wider one, rather than colliding
+    let uuid = u128::MAX;
+    let site = sites.register(uuid);
+    assert!(site > NARROW);
+    assert_eq!(sites.uuid(site), Some(uuid));
+    assert_eq!(sites.register(uuid), site);
+
+    let mut storage = Storage {
+        site,
+        uuid,
+        sites,
+        ..Storage::with_strategy(crate::Strategy::NonInterleaving(16))
+    };
+
+    let ops = storage.append("wide".chars());
+    assert!(ops.iter().all(|op| op.position().site_id() == site));
+    assert!(ops.iter().all(|op| op.position().is_heap()));
+
+    let bytes = crate::wire::encode(&ops).unwrap();
+    let mut replica = Storage::default();
+    let decoded = crate::wire::decode(&bytes, &mut replica).unwrap();
+    for op in &decoded {
+        assert!(replica.apply(op));
+    }
+
+    // the wire format carries the UUID, which the replica maps to its own id
+    assert_eq!(replica.string(..), "wide");
+    let site = decoded[0].position().site_id();
+    assert_eq!(replica.sites().uuid(site), Some(uuid));
+}
@@ -0,0 +1,157 @@
+use quickcheck_macros::quickcheck;
+
+use super::*;
+
+/// Creates a replica of `base` at `site`, then applies the (arbitrary) local `edits`.
+///
+/// Each edit is an index into the document followed by either a character to insert
+/// before it, or `None` to remove the character there.
+fn replica(base: &Storage, site: u32, edits: &[(u8, Option<char>)]) -> Storage {
+    let mut storage = Storage::with_uuid(site as u128);
+    storage.merge(base);
+
+    for (index, edit) in edits {
+        let len = storage.characters(..).count();
+        match edit {
+            Some(ch) => {
+                let before = storage
+                    .characters(..)
+                    .nth(*index as usize % (len + 1))
+                    .map(|(pos, _)| pos.clone())
+                    .unwrap_or_else(Position::last);
+                let _ = storage.insert(*ch, &before);
+            }
+            None if len > 0 => {
+                let pos = storage
+                    .characters(..)
+                    .nth(*index as usize % len)
+                    .map(|(pos, _)| pos.clone())
+                    .unwrap();
+                storage.remove(&pos);
+            }
+            None => {}
+        }
+    }
+
+    storage
+}
+
+fn merged<'a>(replicas: impl IntoIterator<Item = &'a Storage>) -> Storage {
+    let mut storage = Storage::default();
+    for replica in replicas {
+        storage.merge(replica);
+    }
+
+    storage
+}
+
+fn state(storage: &Storage) -> (Vec<(&Position, &char)>, &Deleted) {
+    (storage.elements.iter().collect(), &storage.deleted)
+}
+
+type Edits = Vec<(u8, Option<char>)>;
+
+#[quickcheck]
+fn merge_is_commutative(base: String, x: Edits, y: Edits) {
+    let base = Storage::from(base);
+    let a = replica(&base, 1, &x);
+    let b = replica(&base, 2, &y);
+
+    let ab = merged([&a, &b]);
+    let ba = merged([&b, &a]);
+
+    assert_eq!(state(&ab), state(&ba));
+}
+
+#[quickcheck]
+fn merge_is_associative(base: String, x: Edits, y: Edits, z: Edits) {
+    let base = Storage::from(base);
+    let a = replica(&base, 1, &x);
+    let b = replica(&base, 2, &y);
+    let c = replica(&base, 3, &z);
+
+    let ab_c = merged([&merged([&a, &b]), &c]);
+    let a_bc = merged([&a, &merged([&b, &c])]);
+
+    assert_eq!(state(&ab_c), state(&a_bc));
+}
+
+#[quickcheck]
+fn merge_is_idempotent(base: String, x: Edits) {
+    let base = Storage::from(base);
+    let a = replica(&base, 1, &x);
+
+    let once = merged([&a]);
+    let twice = merged([&a, &a]);
+
+    assert_eq!(state(&once), state(&twice));
+    assert_eq!(once.string(..), a.string(..));
+}
+
+#[quickcheck]
+fn inserts_land_where_expected(edits: Vec<(u8, char)>, strategy: u8) {
+    let mut storage = Storage::with_strategy(match strategy % 5 {
+        0 => Strategy::Boundary,
+        1 => Strategy::BoundaryPlus(16),
+        2 => Strategy::BoundaryMinus(16),
+        3 => Strategy::Boundaries(16),
+        _ => Strategy::NonInterleaving(16),
+    });
+    let mut expected = Vec::new();
+
+    for (index, ch) in edits {
+        let index = index as usize % (expected.len() + 1);
+        let before = storage
+            .characters(..)
+            .nth(index)
+            .map(|(pos, _)| pos.clone())
+            .unwrap_or_else(Position::last);
+
+        assert!(storage.insert(ch, &before).is_some());
+        expected.insert(index, ch);
+    }
+
+    assert_eq!(storage.string(..), String::from_iter(expected));
+}
+
+#[test]
+fn colliding_sites() {
+    // two UUIDs that start their search for a site id from the same slot
+    let mut a = Storage::with_uuid(1);
+    let mut b = Storage::with_uuid(1 + u16::MAX as u128);
+    assert_eq!(a.site, b.site);
+
+    let x = a.append("xy".chars());
+    let y = b.append("z".chars());
+
+    // each learns of the other from the wire, and one of them moves to another id…
+    let bytes = wire::encode(&y).unwrap();
+    for op in wire::decode(&bytes, &mut a).unwrap() {
+        assert!(a.apply(&op));
+    }
+
+    let bytes = wire::encode(&x).unwrap();
+    for op in wire::decode(&bytes, &mut b).unwrap() {
+        assert!(b.apply(&op));
+    }
+
+    // …so that their positions mean the same thing at both, and merging changes neither
+    assert_ne!(a.site, b.site);
+    assert_eq!(a.sites(), b.sites());
+    assert_eq!(state(&a), state(&b));
+
+    a.merge(&b);
+    b.merge(&a);
+    assert_eq!(a.string(..).len(), 3);
+    assert_eq!(state(&a), state(&b));
+
+    // as do replicas that only ever merge
+    let mut c = Storage::with_uuid(1);
+    let mut d = Storage::with_uuid(1 + u16::MAX as u128);
+    c.extend("xy".chars());
+    d.extend("z".chars());
+    c.merge(&d);
+    d.merge(&c);
+    assert_eq!(c.string(..).len(), 3);
+    assert_eq!(state(&c), state(&d));
+}
@@ -0,0 +1,205 @@
+use std::collections::BTreeMap;
+use std::ops::{Deref, Range, RangeBounds};
+
+use crate::{Element, Operation, Origin, Position, Storage};
+
+/// A batch of edits made as one change: with a single `clock` tick, so a single [`Dot`](crate::Dot),
+/// a single [`ChangeEvent`](crate::ChangeEvent), and a single list of operations to ship — as one
+/// [`wire::encode()`](crate::wire::encode) batch, for instance.
+///
+/// It has the editing methods of the [`Storage`] it borrows, and dereferences to it for everything else.
+pub struct Transaction<'a, T = char> {
+    storage: &'a mut Storage<T>,
+    ops: Vec<Operation<T>>,
+    removed: Option<BTreeMap<Position, T>>, // the values of removed elements, for an `UndoManager`
+}
+
+impl<T: Element> Storage<T> {
+    /// Makes the edits of `f` as one change, returning the [`Operation`]s needed to replicate it.
+    ///
+    /// Like [`Storage::append()`] does for a run, the `clock` is only ticked once; and not at all
+    /// if nothing changes. So a paste or a find-and-replace is a single edit, rather than thousands.
+    pub fn transaction(&mut self, f: impl FnOnce(&mut Transaction<T>)) -> Vec<Operation<T>> {
+        self.transaction_keeping(false, f).0
+    }
+
+    /// Makes the edits of `f` as one change, as [`Storage::transaction()`]; returning, too, the
+    /// values of the elements it removed, if asked to `keep` them.
+    pub(crate) fn transaction_keeping(
+        &mut self,
+        keep: bool,
+        f: impl FnOnce(&mut Transaction<T>),
+    ) -> (Vec<Operation<T>>, BTreeMap<Position, T>) {
+        self.transaction = Some(None);
+
+        let mut transaction = Transaction {
+            storage: self,
+            ops: Vec::new(),
+            removed: keep.then(BTreeMap::new),
+        };
+
+        f(&mut transaction);
+        let ops = std::mem::take(&mut transaction.ops);
+        let removed = transaction.removed.take().unwrap_or_default();
+
+        drop(transaction); // closing it; as it is, should `f` panic instead
+        self.notify(&ops, Origin::Local); // just the once
+        (ops, removed)
+    }
+}
+
+impl<T: Element> Transaction<'_, T> {
+    /// As [`Storage::insert()`].
+    pub fn insert(&mut self, value: T, before: &Position) -> Option<&Operation<T>> {
+        let op = self.storage.insert(value, before)?;
+        self.ops.push(op);
+        self.ops.last()
+    }
+
+    /// As [`Storage::remove()`].
+    pub fn remove(&mut self, pos: &Position) -> Option<&Operation<T>> {
+        self.keep(pos.clone()..=pos.clone(), 1);
+        let op = self.storage.remove(pos)?;
+        self.ops.push(op);
+        self.ops.last()
+    }
+
+    /// As [`Storage::append()`].
+    pub fn append(&mut self, iter: impl IntoIterator<Item = T>) {
+        let ops = self.storage.append(iter);
+        self.ops.extend(ops);
+    }
+
+    /// As [`Storage::insert_at()`].
+    pub fn insert_at(&mut self, index: usize, value: T) -> Option<&Operation<T>> {
+        let op = self.storage.insert_at(index, value)?;
+        self.ops.push(op);
+        self.ops.last()
+    }
+
+    /// As [`Storage::insert_all_at()`].
+    pub fn insert_all_at(&mut self, index: usize, iter: impl IntoIterator<Item = T>) {
+        let ops = self.storage.insert_all_at(index, iter);
+        self.ops.extend(ops);
+    }
+
+    /// As [`Storage::remove_at()`].
+    pub fn remove_at(&mut self, index: usize) -> Option<&Operation<T>> {
+        let pos = self.storage.position_at(index)?.clone();
+        self.keep(pos.., 1);
+        let op = self.storage.remove_at(index)?;
+        self.ops.push(op);
+        self.ops.last()
+    }
+
+    /// As [`Storage::remove_range()`].
+    pub fn remove_range(&mut self, range: Range<usize>) {
+        if let Some(pos) = self.storage.position_at(range.start).cloned() {
+            self.keep(pos.., range.len());
+        }
+
+        let ops = self.storage.remove_range(range);
+        self.ops.extend(ops);
+    }
+
+    /// Keeps the values of the first `count` elements in the `range` of positions, that are about
+    /// to be removed; should they be wanted.
+    fn keep(&mut self, range: impl RangeBounds<Position>, count: usize) {
+        if let Some(removed) = &mut self.removed {
+            let elements = self.storage.elements(range).take(count);
+            removed.extend(elements.map(|(pos, value)| (pos.clone(), value.clone())));
+        }
+    }
+}
+
+impl Transaction<'_> {
+    /// As [`Storage::insert_str_at()`].
+    pub fn insert_str_at(&mut self, index: usize, str: &str) {
+        let ops = self.storage.insert_str_at(index, str);
+        self.ops.extend(ops);
+    }
+}
+
+impl<T> Drop for Transaction<'_, T> {
+    fn drop(&mut self) {
+        self.storage.transaction = None;
+    }
+}
+
+impl<T> Deref for Transaction<'_, T> {
+    type Target = Storage<T>;
+
+    fn deref(&self) -> &Self::Target {
+        self.storage
+    }
+}
+
+#[test]
+fn find_and_replace() {
+    let mut a = Storage::from("one two one two one");
+    let mut b = Storage::with_uuid(2);
+    for op in a.operations_since(b.version()) {
+        b.apply(&op);
+    }
+
+    let clock = a.clock;
+    let ops = a.transaction(|tx| {
+        while let Some(index) = tx.string(..).find("one") {
+            tx.remove_range(index..index + 3);
+            tx.insert_str_at(index, "1");
+        }
+    });
+
+    assert_eq!(a.string(..), "1 two 1 two 1");
+    assert_eq!(a.clock, clock + 1);
+    assert!(ops.iter().all(|op| op.dot().clock == clock + 1));
+
+    // a single message brings another replica up-to-date
+    let bytes = crate::wire::encode(&ops).unwrap();
+    for op in crate::wire::decode(&bytes, &mut b).unwrap() {
+        b.apply(&op);
+    }
+    assert_eq!(b.string(..), a.string(..));
+
+    // nothing changed, so the `clock` isn’t ticked
+    assert!(a
+        .transaction(|tx| assert!(tx.remove_at(99).is_none()))
+        .is_empty());
+    assert_eq!(a.clock, clock + 1);
+}
+
+#[test]
+fn reinserting_in_place() {
+    let mut storage = Storage::with_strategy(crate::Strategy::Boundary);
+    let ops = storage.transaction(|tx| {
+        for _ in 0..3 {
+            tx.insert_at(0, 'a');
+            tx.remove_at(0);
+        }
+        tx.insert_at(0, 'b');
+    });
+
+    // the same path is generated each time; but, with the same `clock`, is skipped once deleted
+    assert_eq!(ops.len(), 7);
+    assert_eq!(storage.string(..), "b");
+}
+
+#[test]
+fn panicking_transactions() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    let mut storage = Storage::default();
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        storage.transaction(|tx| {
+            tx.append("ab".chars());
+            panic!("part-way through");
+        })
+    }));
+    assert!(result.is_err());
+
+    // the edits made before the panic are kept, but later ones are their own
+    let x = storage.append("c".chars()).remove(0);
+    let y = storage.append("d".chars()).remove(0);
+    assert_eq!(storage.string(..), "abcd");
+    assert_ne!(x.dot(), y.dot());
+}
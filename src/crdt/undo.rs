@@ -0,0 +1,403 @@
+use std::collections::BTreeSet;
+use std::ops::Bound::{Excluded, Unbounded};
+use std::ops::Range;
+
+use crate::{Element, Operation, Position, Storage, Transaction};
+
+/// Undoes, and redoes, the edits made at this site; leaving those of every other site be.
+///
+/// Edits are made through the manager, rather than the [`Storage`] itself, so that it can keep
+/// what it needs to revert them — the value of each removed element, in particular. The edits of
+/// a single call are undone together, as are those of a [`UndoManager::transaction()`], or made
+/// within a [`UndoManager::group()`]. Edits made to the `Storage` directly aren’t recorded.
+///
+/// Reverting an edit is itself an edit: removed elements are re-inserted, at fresh positions
+/// between their original neighbours, and the [`Operation`]s returned can be broadcast like
+/// any other.
+pub struct UndoManager<T = char> {
+    undo: Vec<Vec<Edit<T>>>,
+    redo: Vec<Vec<Edit<T>>>,
+    open: Option<Vec<Edit<T>>>, // the group being recorded
+}
+
+/// An edit, as needed to revert it; its position along with the UUID of the site that made it,
+/// as that site may yet be given a new id.
+enum Edit<T> {
+    Inserted(Position, u128),
+    Removed(Position, u128, T),
+}
+
+impl<T> Default for UndoManager<T> {
+    fn default() -> Self {
+        UndoManager {
+            undo: Vec::new(),
+            redo: Vec::new(),
+            open: None,
+        }
+    }
+}
+
+impl<T: Element> UndoManager<T> {
+    /// Inserts `value`, as [`Storage::insert()`].
+    #[must_use]
+    pub fn insert(
+        &mut self,
+        storage: &mut Storage<T>,
+        value: T,
+        before: &Position,
+    ) -> Option<Operation<T>> {
+        let op = storage.insert(value, before)?;
+        self.record_inserts(storage, std::slice::from_ref(&op));
+        Some(op)
+    }
+
+    /// Removes the element at `pos`, as [`Storage::remove()`].
+    pub fn remove(&mut self, storage: &mut Storage<T>, pos: &Position) -> Option<Operation<T>> {
+        let value = storage.get(pos)?.clone();
+        let uuid = storage.sites().uuid(pos.site_id())?;
+        let op = storage.remove(pos)?;
+        self.record(vec![Edit::Removed(pos.clone(), uuid, value)]);
+        Some(op)
+    }
+
+    /// Appends the elements, as [`Storage::append()`].
+    pub fn append(
+        &mut self,
+        storage: &mut Storage<T>,
+        iter: impl IntoIterator<Item = T>,
+    ) -> Vec<Operation<T>> {
+        let ops = storage.append(iter);
+        self.record_inserts(storage, &ops);
+        ops
+    }
+
+    /// Inserts `value` at `index`, as [`Storage::insert_at()`].
+    #[must_use]
+    pub fn insert_at(
+        &mut self,
+        storage: &mut Storage<T>,
+        index: usize,
+        value: T,
+    ) -> Option<Operation<T>> {
+        let op = storage.insert_at(index, value)?;
+        self.record_inserts(storage, std::slice::from_ref(&op));
+        Some(op)
+    }
+
+    /// Inserts the elements at `index`, as [`Storage::insert_all_at()`].
+    pub fn insert_all_at(
+        &mut self,
+        storage: &mut Storage<T>,
+        index: usize,
+        iter: impl IntoIterator<Item = T>,
+    ) -> Vec<Operation<T>> {
+        let ops = storage.insert_all_at(index, iter);
+        self.record_inserts(storage, &ops);
+        ops
+    }
+
+    /// Removes the element at `index`, as [`Storage::remove_at()`].
+    pub fn remove_at(&mut self, storage: &mut Storage<T>, index: usize) -> Option<Operation<T>> {
+        let pos = storage.position_at(index)?.clone();
+        self.remove(storage, &pos)
+    }
+
+    /// Removes the elements within the `range` of indices, as [`Storage::remove_range()`].
+    pub fn remove_range(
+        &mut self,
+        storage: &mut Storage<T>,
+        range: Range<usize>,
+    ) -> Vec<Operation<T>> {
+        self.transaction(storage, |tx| tx.remove_range(range))
+    }
+
+    /// Makes the edits of `f` as one change, as [`Storage::transaction()`]; to be undone together.
+    pub fn transaction(
+        &mut self,
+        storage: &mut Storage<T>,
+        f: impl FnOnce(&mut Transaction<T>),
+    ) -> Vec<Operation<T>> {
+        let (ops, mut removed) = storage.transaction_keeping(true, f);
+
+        // an element both inserted and removed within it was never seen, so needn’t be reverted
+        let mut inserted = BTreeSet::new();
+        let mut edits = Vec::new();
+        for op in &ops {
+            match op {
+                Operation::Insert { pos, .. } => {
+                    inserted.insert(pos);
+                    if storage.get(pos).is_some() {
+                        edits.push(Edit::Inserted(pos.clone(), storage.uuid()));
+                    }
+                }
+                Operation::Delete { pos, .. } if !inserted.contains(pos) => {
+                    let uuid = storage.sites().uuid(pos.site_id());
+                    if let (Some(uuid), Some(value)) = (uuid, removed.remove(pos)) {
+                        edits.push(Edit::Removed(pos.clone(), uuid, value));
+                    }
+                }
+                Operation::Delete { .. } => {}
+            }
+        }
+
+        self.record(edits);
+        ops
+    }
+
+    /// Records the edits made by `f` as one, to be undone together.
+    pub fn group<R>(&mut self, f: impl FnOnce(&mut Self) -> R) -> R {
+        if self.open.is_some() {
+            return f(self); // nested within an enclosing group
+        }
+
+        self.open = Some(Vec::new());
+        let result = f(self);
+
+        // SAFETY: only this method takes it
+        let edits = self.open.take().unwrap();
+        if !edits.is_empty() {
+            self.undo.push(edits);
+        }
+
+        result
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// Reverts the most recent group of edits, returning the [`Operation`]s needed to replicate it.
+    ///
+    /// Inserted elements that have since been removed, here or elsewhere, are left as they are.
+    pub fn undo(&mut self, storage: &mut Storage<T>) -> Vec<Operation<T>> {
+        let Some(edits) = self.undo.pop() else {
+            return Vec::new();
+        };
+
+        let (ops, inverse) = self.revert(storage, edits);
+        self.redo.push(inverse);
+        ops
+    }
+
+    /// Reapplies the most recently undone group of edits, returning the [`Operation`]s needed to
+    /// replicate it.
+    pub fn redo(&mut self, storage: &mut Storage<T>) -> Vec<Operation<T>> {
+        let Some(edits) = self.redo.pop() else {
+            return Vec::new();
+        };
+
+        let (ops, inverse) = self.revert(storage, edits);
+        self.undo.push(inverse);
+        ops
+    }
+
+    fn record_inserts(&mut self, storage: &Storage<T>, ops: &[Operation<T>]) {
+        let edits = ops
+            .iter()
+            .map(|op| Edit::Inserted(op.position().clone(), storage.uuid()));
+        self.record(edits.collect());
+    }
+
+    fn record(&mut self, mut edits: Vec<Edit<T>>) {
+        if edits.is_empty() {
+            return;
+        }
+
+        self.redo.clear();
+        match &mut self.open {
+            Some(open) => open.append(&mut edits),
+            None => self.undo.push(edits),
+        }
+    }
+
+    /// Reverts the `edits`, latest first, returning the operations and the edits that undo them in turn.
+    fn revert(
+        &mut self,
+        storage: &mut Storage<T>,
+        edits: Vec<Edit<T>>,
+    ) -> (Vec<Operation<T>>, Vec<Edit<T>>) {
+        let mut ops = Vec::new();
+        let mut inverse = Vec::new();
+        let mut restored = Vec::new(); // the (old, new) positions of re-inserted elements
+
+        for edit in edits.into_iter().rev() {
+            match edit {
+                Edit::Inserted(pos, uuid) => {
+                    let pos = storage.local(&pos, uuid);
+                    if let Some(value) = storage.get(&pos).cloned() {
+                        ops.extend(storage.remove(&pos));
+                        inverse.push(Edit::Removed(pos, uuid, value));
+                    }
+                }
+                Edit::Removed(pos, uuid, value) => {
+                    let pos = storage.local(&pos, uuid);
+                    let before = Self::anchor(storage, &pos, &restored);
+                    if let Some(op) = storage.insert(value, &before) {
+                        restored.push((pos, op.position().clone()));
+                        inverse.push(Edit::Inserted(op.position().clone(), storage.uuid()));
+                        ops.push(op);
+                    }
+                }
+            }
+        }
+
+        // later edits to the re-inserted elements refer to their new positions
+        for (old, new) in &restored {
+            for edit in self.undo.iter_mut().chain(&mut self.redo).flatten() {
+                match edit {
+                    Edit::Inserted(pos, uuid) | Edit::Removed(pos, uuid, _)
+                        if storage.local(pos, *uuid) == *old =>
+                    {
+                        (*pos, *uuid) = (new.clone(), storage.uuid());
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        inverse.reverse(); // so that it, too, is in the order it was made
+        (ops, inverse)
+    }
+
+    /// Returns the position to re-insert the element that was at `pos` before: the one that now
+    /// follows it, be it an element that was already there or one already `restored`.
+    fn anchor(storage: &Storage<T>, pos: &Position, restored: &[(Position, Position)]) -> Position {
+        let following = storage
+            .elements
+            .range((Excluded(pos), Unbounded))
+            .map(|(pos, _)| pos)
+            .find(|pos| restored.iter().all(|(_, new)| new != *pos));
+
+        let nearest = restored
+            .iter()
+            .filter(|(old, _)| old > pos)
+            .min_by(|(a, _), (b, _)| a.cmp(b));
+
+        match (following, nearest) {
+            (Some(following), Some((old, new))) if old < following => new.clone(),
+            (None, Some((_, new))) => new.clone(),
+            (Some(following), _) => following.clone(),
+            (None, None) => Position::last(),
+        }
+    }
+}
+
+impl UndoManager {
+    /// Inserts the string at `index`, as [`Storage::insert_str_at()`].
+    pub fn insert_str_at(
+        &mut self,
+        storage: &mut Storage,
+        index: usize,
+        str: &str,
+    ) -> Vec<Operation> {
+        self.insert_all_at(storage, index, str.chars())
+    }
+}
+
+#[test]
+fn undoing_local_edits() {
+    let mut a = Storage::with_uuid(1);
+    let mut b = Storage::with_uuid(2);
+    let mut undo = UndoManager::default();
+
+    let sync = |ops: Vec<Operation>, to: &mut Storage| {
+        for op in ops {
+            to.apply(&op);
+        }
+    };
+
+    sync(undo.append(&mut a, "hello".chars()), &mut b);
+    sync(b.append(" world".chars()), &mut a);
+
+    // remove “ell”, all at once
+    let ops = undo.group(|undo| {
+        let positions: Vec<_> = (1..4).map(|n| a.position_at(n).unwrap().clone()).collect();
+        positions
+            .iter()
+            .filter_map(|pos| undo.remove(&mut a, pos))
+            .collect::<Vec<_>>()
+    });
+    sync(ops, &mut b);
+    assert_eq!(a.string(..), "ho world");
+
+    // only the local site’s edits are undone…
+    sync(undo.undo(&mut a), &mut b);
+    assert_eq!(a.string(..), "hello world");
+    sync(undo.undo(&mut a), &mut b);
+    assert_eq!(a.string(..), " world");
+    assert!(!undo.can_undo());
+
+    // …and redone, the re-inserted elements included
+    sync(undo.redo(&mut a), &mut b);
+    assert_eq!(a.string(..), "hello world");
+    sync(undo.redo(&mut a), &mut b);
+    assert_eq!(a.string(..), "ho world");
+    assert!(!undo.can_redo());
+
+    assert_eq!(b.string(..), a.string(..));
+}
+
+#[test]
+fn renumbered_sites() {
+    use crate::Merge;
+
+    // two UUIDs given the same site id, until each learns of the other
+    let mut a = Storage::with_uuid(1 + u16::MAX as u128);
+    let mut b = Storage::with_uuid(1);
+    let mut undo = UndoManager::default();
+
+    let _ = undo.append(&mut a, "hello".chars());
+    let pos = a.position_at(0).unwrap().clone();
+    let _ = undo.remove(&mut a, &pos);
+
+    // a’s site moves out of the way of b’s, the positions of its edits along with it
+    b.append("!".chars());
+    a.merge(&b);
+    assert_ne!(a.sites().site_id(a.uuid()), Some(pos.site_id()));
+
+    let ops = undo.undo(&mut a);
+    assert_eq!(ops.len(), 1);
+    assert_eq!(a.string(..).matches('h').count(), 1);
+    let _ = undo.undo(&mut a);
+    assert_eq!(a.string(..), "!");
+}
+
+#[test]
+fn undoing_offset_edits() {
+    let mut storage = Storage::with_uuid(1);
+    let mut undo = UndoManager::default();
+
+    let _ = undo.insert_str_at(&mut storage, 0, "abcdefgh");
+    let _ = undo.remove_range(&mut storage, 6..8);
+    let _ = undo.insert_at(&mut storage, 0, '>');
+    let _ = undo.remove_at(&mut storage, 2);
+    assert_eq!(storage.string(..), ">acdef");
+
+    // a transaction is undone as one, leaving out what it both inserted and removed
+    let _ = undo.transaction(&mut storage, |tx| {
+        tx.insert_str_at(4, "XY");
+        tx.remove_at(5);
+        tx.remove_at(5);
+    });
+    assert_eq!(storage.string(..), ">acdXf");
+
+    let mut states = Vec::new();
+    while undo.can_undo() {
+        let _ = undo.undo(&mut storage);
+        states.push(storage.string(..));
+    }
+    assert_eq!(states, [">acdef", ">abcdef", "abcdef", "abcdefgh", ""]);
+
+    let _ = undo.redo(&mut storage);
+    let _ = undo.redo(&mut storage);
+    assert_eq!(storage.string(..), "abcdef");
+
+    // while edits made to the storage directly aren’t recorded
+    storage.remove_range(0..2);
+    let _ = undo.undo(&mut storage);
+    assert_eq!(storage.string(..), "cdefgh");
+}
@@ -0,0 +1,370 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use itertools::Itertools;
+
+use crate::crdt::sites::Renames;
+use crate::{Element, Merge, Operation, Position, Storage};
+
+/// Identifies an edit by the site that made it and that site’s `clock` at the time.
+///
+/// The characters inserted by a single call share a dot, as do those removed by one.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_crate::Serialize, serde_crate::Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct Dot {
+    pub site: u32,
+    pub clock: u64,
+}
+
+/// The latest `clock` seen from each site.
+///
+/// Clocks are partially ordered: one vector is before another if it has seen no more
+/// of any site’s edits, and all of them are concurrent with one another otherwise.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_crate::Serialize, serde_crate::Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct VersionVector {
+    clocks: BTreeMap<u32, u64>,
+}
+
+impl VersionVector {
+    /// Returns the latest `clock` seen from `site`; zero if it has seen none of them.
+    pub fn get(&self, site: u32) -> u64 {
+        self.clocks.get(&site).copied().unwrap_or_default()
+    }
+
+    /// Returns whether the edit identified by `dot` has been seen.
+    pub fn includes(&self, dot: Dot) -> bool {
+        dot.clock <= self.get(dot.site)
+    }
+
+    /// Records that the edit identified by `dot` has been seen.
+    pub fn observe(&mut self, dot: Dot) {
+        let clock = self.clocks.entry(dot.site).or_default();
+        *clock = Ord::max(*clock, dot.clock);
+    }
+
+    /// Iterates over the latest `clock` seen from each site.
+    pub fn iter(&self) -> impl Iterator<Item = Dot> + '_ {
+        self.clocks
+            .iter()
+            .map(|(&site, &clock)| Dot { site, clock })
+    }
+
+    /// Returns this vector, with the sites in `renames` renumbered.
+    pub(crate) fn renamed(&self, renames: &Renames) -> VersionVector {
+        let clocks = self.iter().map(|dot| dot.renamed(renames));
+        VersionVector {
+            clocks: clocks.map(|dot| (dot.site, dot.clock)).collect(),
+        }
+    }
+}
+
+impl Position {
+    /// Returns the [`Dot`] of the insert that created this position.
+    pub fn dot(&self) -> Dot {
+        Dot {
+            site: self.site_id(),
+            clock: self.clock(),
+        }
+    }
+}
+
+impl Merge for VersionVector {
+    /// The latest `clock` of each site in either vector.
+    fn merge(&mut self, other: &Self) {
+        for dot in other.iter() {
+            self.observe(dot);
+        }
+    }
+}
+
+impl PartialOrd for VersionVector {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        let le = self.iter().all(|dot| other.includes(dot));
+        let ge = other.iter().all(|dot| self.includes(dot));
+
+        match (le, ge) {
+            (true, true) => Some(Ordering::Equal),
+            (true, false) => Some(Ordering::Less),
+            (false, true) => Some(Ordering::Greater),
+            (false, false) => None,
+        }
+    }
+}
+
+impl<T: Element> Storage<T> {
+    /// Returns the latest `clock` seen from each site.
+    pub fn version(&self) -> &VersionVector {
+        &self.version
+    }
+
+    /// Returns the operations needed to bring a replica, that has seen `version`, up-to-date.
+    ///
+    /// Operations are ordered by the site, and then the `clock`, that made them. As nothing is
+    /// kept of the elements that have since been removed, their inserts can’t be sent; a delete
+    /// made with the insert’s own [`Dot`] is sent in place of each, which removes the element
+    /// should it arrive by some other route, and stands in for the insert in a [`CausalBuffer`].
+    pub fn operations_since(&self, version: &VersionVector) -> Vec<Operation<T>> {
+        let inserts = self
+            .elements
+            .iter()
+            .filter(|(pos, _)| !version.includes(pos.dot()))
+            .map(|(pos, value)| Operation::insert(pos.clone(), value.clone(), &self.sites));
+
+        let removed = self
+            .deleted
+            .iter()
+            .map(|(pos, _)| pos)
+            .dedup()
+            .filter(|pos| !version.includes(pos.dot()))
+            .map(|pos| Operation::delete(pos.clone(), pos.dot(), &self.sites));
+
+        let deletes = self
+            .deleted
+            .iter()
+            .filter(|(pos, dot)| !version.includes(*dot) && *dot != pos.dot())
+            .map(|(pos, dot)| Operation::delete(pos, dot, &self.sites));
+
+        let mut ops: Vec<_> = inserts.chain(removed).chain(deletes).collect();
+        ops.sort_by_key(Operation::dot); // stable, so a run stays in order
+        ops
+    }
+}
+
+/// Holds the remote operations that arrive before those they depend upon.
+///
+/// An operation is delivered once the previous edit made by its site has been, and — for a
+/// delete — once the insert it removes has been too. So it suits transports that reorder
+/// or repeat messages; although every operation must arrive eventually. The version of the
+/// document only moves past an edit once one of its operations has been applied; a delete made
+/// with the [`Dot`] of the insert it removes, as [`Storage::operations_since()`] sends in place
+/// of the inserts of removed elements, counts as one.
+pub struct CausalBuffer<T = char> {
+    pending: Vec<Operation<T>>,
+}
+
+impl<T> Default for CausalBuffer<T> {
+    fn default() -> Self {
+        CausalBuffer {
+            pending: Vec::new(),
+        }
+    }
+}
+
+impl<T: Element> CausalBuffer<T> {
+    /// Returns the number of operations waiting on others.
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Applies `op` to the `storage` if it is ready, along with any it was holding up; otherwise
+    /// holds on to it.
+    ///
+    /// Returns the operations that were applied, in the order they were.
+    /// The sites `op` mentions are registered straight away; an operation that doesn’t say which
+    /// they are is dropped, as it could never be applied.
+    pub fn receive(&mut self, storage: &mut Storage<T>, op: Operation<T>) -> Vec<Operation<T>> {
+        if storage.localize(&op).is_err() {
+            return Vec::new();
+        }
+
+        self.pending.push(op);
+
+        let mut delivered = Vec::new();
+        while let Some(n) = self
+            .pending
+            .iter()
+            .position(|op| Self::is_ready(storage, op))
+        {
+            let op = self.pending.remove(n);
+            storage.apply(&op);
+            delivered.push(op);
+        }
+
+        delivered
+    }
+
+    fn is_ready(storage: &Storage<T>, op: &Operation<T>) -> bool {
+        // the site ids of a pending operation are those of the replica that made it; and those
+        // here may have moved since it arrived
+        let local = |dot: Dot| {
+            let uuid = op.sites().uuid(dot.site)?;
+            Some(Dot {
+                site: storage.sites().site_id(uuid)?,
+                clock: dot.clock,
+            })
+        };
+        let (Some(dot), Some(inserted)) = (local(op.dot()), local(op.position().dot())) else {
+            return false;
+        };
+
+        let version = storage.version();
+        let previous = version.get(dot.site) >= dot.clock.saturating_sub(1);
+
+        match op {
+            Operation::Insert { .. } => previous,
+            Operation::Delete { .. } if inserted == dot => previous, // an insert, since removed
+            Operation::Delete { .. } => previous && version.includes(inserted),
+        }
+    }
+}
+
+#[test]
+fn partial_order() {
+    let dot = |site, clock| Dot { site, clock };
+
+    let mut a = VersionVector::default();
+    let mut b = VersionVector::default();
+    assert_eq!(a.partial_cmp(&b), Some(Ordering::Equal));
+
+    a.observe(dot(1, 2));
+    assert!(b < a);
+
+    b.observe(dot(2, 1));
+    assert_eq!(a.partial_cmp(&b), None);
+
+    a.merge(&b);
+    assert!(b < a);
+    assert!(a.includes(dot(1, 1)));
+    assert!(!a.includes(dot(1, 3)));
+}
+
+#[test]
+fn missing_operations() {
+    let mut a = Storage::with_uuid(1);
+    let mut b = Storage::with_uuid(2);
+
+    let ops = a.append("hello".chars());
+    for op in &ops {
+        b.apply(op);
+    }
+
+    let version = b.version().clone();
+    let _ = a.remove(ops[0].position());
+    let _ = a.insert_at(4, '!');
+    let _ = b.insert_str_at(0, "oh, ");
+
+    let missing = a.operations_since(&version);
+    assert_eq!(missing.len(), 2);
+    for op in missing {
+        b.apply(&op);
+    }
+
+    for op in b.operations_since(a.version()) {
+        a.apply(&op);
+    }
+
+    assert_eq!(a.string(..), "oh, ello!");
+    assert_eq!(a.string(..), b.string(..));
+    assert_eq!(a.version(), b.version());
+    assert!(a.operations_since(b.version()).is_empty());
+}
+
+#[test]
+fn causal_delivery() {
+    let mut a = Storage::with_uuid(1);
+    let mut b = Storage::with_uuid(2);
+    let mut c = Storage::with_uuid(3);
+
+    let mut ops = a.append("ab".chars());
+    ops.extend(a.append("cd".chars()));
+    for op in &ops {
+        b.apply(op);
+    }
+    ops.extend(b.remove(ops[2].position())); // b deletes what a inserted
+
+    // c hears of them in reverse
+    let mut buffer = CausalBuffer::default();
+    let mut delivered = Vec::new();
+    for op in ops.iter().rev() {
+        delivered.extend(buffer.receive(&mut c, op.clone()));
+        delivered.extend(buffer.receive(&mut c, op.clone())); // and twice
+    }
+
+    assert!(buffer.is_empty());
+    assert_eq!(delivered.len(), 2 * ops.len());
+    assert_eq!(c.string(..), "abd");
+    assert_eq!(c.string(..), b.string(..));
+}
+
+#[test]
+fn removed_inserts_are_not_waited_upon() {
+    let mut a = Storage::with_uuid(1);
+    let mut b = Storage::with_uuid(2);
+
+    a.append("a".chars());
+    let pos = a.append("b".chars())[0].position().clone();
+    a.append("c".chars());
+    let _ = a.remove(&pos);
+
+    // the insert of "b" is omitted, having been removed; leaving a gap in a’s clock
+    let mut buffer = CausalBuffer::default();
+    for op in a.operations_since(&VersionVector::default()) {
+        buffer.receive(&mut b, op);
+    }
+
+    assert!(buffer.is_empty());
+    assert_eq!(b.string(..), "ac");
+    assert_eq!(b.version(), a.version());
+    assert!(b.is_deleted(&pos));
+}
+
+#[test]
+fn runs_are_waited_upon() {
+    let mut a = Storage::with_uuid(1);
+    let mut b = Storage::with_uuid(2);
+
+    let run = a.append("ab".chars());
+    let x = a.insert_at(1, 'x').unwrap();
+    let delete = a.remove(run[0].position()).unwrap();
+
+    // the delete of “a”, and the insert that follows its run, overtake the whole run
+    let mut buffer = CausalBuffer::default();
+    assert!(buffer.receive(&mut b, delete.clone()).is_empty());
+    assert!(buffer.receive(&mut b, x.clone()).is_empty());
+
+    let delivered = buffer.receive(&mut b, run[1].clone());
+    assert_eq!(delivered, [run[1].clone(), x, delete]);
+    assert!(buffer.receive(&mut b, run[0].clone()).len() == 1);
+    assert_eq!(b.string(..), "xb");
+}
+
+#[test]
+fn renumbered_sites() {
+    // two UUIDs given the same site id, until each learns of the other
+    let mut a = Storage::with_uuid(1);
+    let mut b = Storage::with_uuid(1 + u16::MAX as u128);
+    b.append("b".chars());
+
+    let mut ops = a.append("hi".chars());
+    ops.extend(a.insert_at(2, '!'));
+    ops.extend(a.remove_at(0));
+
+    // b moves its own site out of the way of a’s, on hearing of it, while the rest are pending
+    let mut buffer = CausalBuffer::default();
+    let mut delivered = Vec::new();
+    for op in ops.into_iter().rev() {
+        delivered.extend(buffer.receive(&mut b, op));
+    }
+
+    assert!(buffer.is_empty());
+    assert_eq!(delivered.len(), 4);
+    assert_ne!(b.sites().site_id(b.uuid()), Some(a.site));
+    assert_eq!(b.string(..).len(), 3);
+    assert!(b.version().includes(Dot {
+        site: b.sites().site_id(a.uuid()).unwrap(),
+        clock: a.clock,
+    }));
+}
@@ -0,0 +1,443 @@
+//! A compact binary encoding of [`Position`]s and [`Operation`]s for keystroke-level sync.
+//!
+//! A batch starts with a table of the UUIDs of every site it mentions, followed by its items:
+//!
+//! ```text
+//! batch    := varint(sites) uuid* varint(items) item*
+//! item     := header [varint(site index) varint(clock)] varint(shared) varint(levels) varint(level)* (varint(char) | [dot])
+//! dot      := varint(site index) varint(clock)
+//! ```
+//!
+//! The `header` byte flags deletes and whether the item has the same site and clock as the
+//! previous one; in which case they are omitted. Each path is delta-encoded against the previous
+//! one: only the number of `shared` leading levels and the differing suffix are written, with the
+//! first level of that suffix written as the (zigzag) difference from the previous path’s value.
+//! As the characters of a single [`Extend`] share a site, a clock and all but the last level of
+//! their path, each costs just a few bytes. Likewise, a delete only carries the [`Dot`] of the
+//! edit that made it when it differs from that of the previous delete.
+
+use crate::{Claims, Dot, Element, Operation, Position, SiteRegistry, Storage, UnknownSite};
+
+const DELETE: u8 = 0b001;
+const REPEAT: u8 = 0b010; // same site and clock as the previous item
+const SAME_DOT: u8 = 0b100; // a delete made by the same edit as the previous one
+
+/// Encodes a batch of operations, translating their site ids to the UUIDs they claim.
+///
+/// Fails should one mention a site without claiming its UUID; as no operation made by a
+/// [`Storage`] does.
+pub fn encode(ops: &[Operation]) -> Result<Vec<u8>, UnknownSite> {
+    let uuid = |op: &Operation, site: u32| op.sites().uuid(site).ok_or(UnknownSite(site));
+
+    let mut table = Vec::new();
+    for op in ops {
+        table.push(uuid(op, op.position().site_id())?);
+        if let Operation::Delete { dot, .. } = op {
+            table.push(uuid(op, dot.site)?);
+        }
+    }
+
+    let mut encoder = Encoder::new(table, ops.len());
+    for op in ops {
+        let site = uuid(op, op.position().site_id())?;
+        match op {
+            Operation::Insert { pos, value, .. } => {
+                encoder.position(pos, site, 0);
+                encoder.varint(*value as u64);
+            }
+            Operation::Delete { pos, dot, .. } => {
+                let dot = (uuid(op, dot.site)?, dot.clock);
+                let same = encoder.dot == Some(dot);
+                encoder.position(pos, site, DELETE | if same { SAME_DOT } else { 0 });
+                if !same {
+                    encoder.site(dot.0);
+                    encoder.varint(dot.1);
+                    encoder.dot = Some(dot);
+                }
+            }
+        }
+    }
+
+    Ok(encoder.bytes)
+}
+
+/// Decodes a batch of operations for the `storage`, registering any sites it doesn’t yet know.
+///
+/// Returns `None` if the `bytes` are not a valid batch, or hold a path the document couldn’t
+/// have generated. Registering a site can renumber the positions of another, that it collides
+/// with; so a batch should be applied before the next is decoded.
+pub fn decode(bytes: &[u8], storage: &mut Storage) -> Option<Vec<Operation>> {
+    let mut decoder = Decoder::new(bytes, storage.sites())?;
+    let ops = (0..decoder.varint()?)
+        .map(|_| {
+            let (pos, header) = decoder.position()?;
+            Some(match header & DELETE {
+                DELETE => {
+                    if header & SAME_DOT == 0 {
+                        decoder.dot = Some(Dot {
+                            site: decoder.site()?,
+                            clock: decoder.varint()?,
+                        });
+                    }
+
+                    let dot = decoder.dot?;
+                    Operation::Delete {
+                        sites: decoder.claims([pos.site_id(), dot.site]),
+                        pos,
+                        dot,
+                    }
+                }
+                _ => Operation::Insert {
+                    sites: decoder.claims([pos.site_id()]),
+                    pos,
+                    value: char::from_u32(u32::try_from(decoder.varint()?).ok()?)?,
+                },
+            })
+        })
+        .collect::<Option<_>>()?;
+
+    decoder.finish(ops, storage)
+}
+
+/// Encodes a batch of positions, as [`encode()`] does operations.
+pub fn encode_positions(
+    positions: &[Position],
+    sites: &SiteRegistry,
+) -> Result<Vec<u8>, UnknownSite> {
+    let table: Vec<u128> = positions
+        .iter()
+        .map(|pos| sites.try_uuid(pos.site_id()))
+        .collect::<Result<_, _>>()?;
+
+    let mut encoder = Encoder::new(table.clone(), positions.len());
+    for (pos, uuid) in positions.iter().zip(table) {
+        encoder.position(pos, uuid, 0);
+    }
+
+    Ok(encoder.bytes)
+}
+
+/// Decodes a batch of positions for the `storage`, as [`decode()`] does operations.
+pub fn decode_positions<T: Element>(
+    bytes: &[u8],
+    storage: &mut Storage<T>,
+) -> Option<Vec<Position>> {
+    let mut decoder = Decoder::new(bytes, storage.sites())?;
+    let positions = (0..decoder.varint()?)
+        .map(|_| decoder.position().map(|(pos, _)| pos))
+        .collect::<Option<_>>()?;
+
+    decoder.finish(positions, storage)
+}
+
+struct Encoder {
+    bytes: Vec<u8>,
+    table: Vec<u128>, // the UUIDs of the sites, by index
+    previous: Option<(u128, u64)>,
+    path: Vec<u32>,
+    dot: Option<(u128, u64)>, // of the previous delete
+}
+
+impl Encoder {
+    /// Starts a batch of `count` items, that mention the sites in `table`.
+    fn new(mut table: Vec<u128>, count: usize) -> Self {
+        table.sort_unstable();
+        table.dedup();
+
+        let mut new = Encoder {
+            bytes: Vec::new(),
+            table,
+            previous: None,
+            path: Vec::new(),
+            dot: None,
+        };
+
+        new.varint(new.table.len() as u64);
+        for uuid in new.table.clone() {
+            new.bytes.extend_from_slice(&uuid.to_le_bytes());
+        }
+
+        new.varint(count as u64);
+        new
+    }
+
+    /// Writes `pos`, made by the site identified by `uuid`.
+    fn position(&mut self, pos: &Position, uuid: u128, mut header: u8) {
+        let current = (uuid, pos.clock());
+        if self.previous == Some(current) {
+            header |= REPEAT;
+        }
+
+        self.bytes.push(header);
+        if header & REPEAT == 0 {
+            self.site(current.0);
+            self.varint(current.1);
+        }
+
+        let path = pos.path();
+        let shared = Iterator::zip(path.iter(), self.path.iter())
+            .take_while(|(p, q)| p == q)
+            .count();
+
+        self.varint(shared as u64);
+        self.varint((path.len() - shared) as u64);
+        if let Some((first, rest)) = path[shared..].split_first() {
+            // within a run, the first differing level is usually just a little past the previous one
+            let previous = self.path.get(shared).copied().unwrap_or(0);
+            self.varint(zigzag(*first as i64 - previous as i64));
+
+            for level in rest {
+                self.varint(*level as u64);
+            }
+        }
+
+        self.previous = Some(current);
+        self.path.clear();
+        self.path.extend_from_slice(path);
+    }
+
+    fn site(&mut self, uuid: u128) {
+        // SAFETY: the table was built from the sites of these very items
+        let index = self.table.binary_search(&uuid).unwrap();
+        self.varint(index as u64);
+    }
+
+    /// [LEB128](https://en.wikipedia.org/wiki/LEB128): seven bits per byte, least significant first.
+    fn varint(&mut self, mut n: u64) {
+        while n >= 0x80 {
+            self.bytes.push(n as u8 | 0x80);
+            n >>= 7;
+        }
+
+        self.bytes.push(n as u8);
+    }
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    uuids: Vec<u128>,
+    table: Vec<u32>, // the site ids the `uuids` have, once registered
+    previous: Option<(u32, u64)>,
+    path: Vec<u32>,
+    dot: Option<Dot>,
+}
+
+impl<'a> Decoder<'a> {
+    /// Reads the table of sites, without registering them until the whole batch has been read.
+    fn new(bytes: &'a [u8], sites: &SiteRegistry) -> Option<Self> {
+        let mut new = Decoder {
+            bytes,
+            uuids: Vec::new(),
+            table: Vec::new(),
+            previous: None,
+            path: Vec::new(),
+            dot: None,
+        };
+
+        for _ in 0..new.varint()? {
+            let (uuid, rest) = new.bytes.split_first_chunk::<16>()?;
+            new.uuids.push(u128::from_le_bytes(*uuid));
+            new.bytes = rest;
+        }
+
+        new.table = match new.uuids.iter().map(|uuid| sites.site_id(*uuid)).collect() {
+            Some(table) => table,
+            None => {
+                // the ids they will have, which may move some of those already known
+                let mut sites = sites.clone();
+                sites.extend(new.uuids.iter().copied());
+                new.uuids
+                    .iter()
+                    .map(|uuid| sites.site_id(*uuid))
+                    .collect::<Option<_>>()?
+            }
+        };
+
+        Some(new)
+    }
+
+    fn position(&mut self) -> Option<(Position, u8)> {
+        let (header, rest) = self.bytes.split_first()?;
+        self.bytes = rest;
+
+        let (site, clock) = match header & REPEAT {
+            REPEAT => self.previous?,
+            _ => (self.site()?, self.varint()?),
+        };
+
+        let shared = usize::try_from(self.varint()?).ok()?;
+        let levels = usize::try_from(self.varint()?).ok()?;
+        if shared > self.path.len() || levels > self.bytes.len() {
+            return None; // each level takes at least one byte
+        }
+
+        let previous = self.path.get(shared).copied().unwrap_or(0);
+        self.path.truncate(shared);
+        for n in 0..levels {
+            let level = match n {
+                0 => u32::try_from(previous as i64 + unzigzag(self.varint()?)).ok()?,
+                _ => u32::try_from(self.varint()?).ok()?,
+            };
+
+            self.path.push(level);
+        }
+
+        self.previous = Some((site, clock));
+        let pos = Position::try_new(site, clock, &self.path).ok()?;
+        Some((pos, *header))
+    }
+
+    fn site(&mut self) -> Option<u32> {
+        let index = usize::try_from(self.varint()?).ok()?;
+        self.table.get(index).copied()
+    }
+
+    /// Returns the UUIDs of the `sites`, that the operation they’re mentioned by claims.
+    fn claims(&self, sites: impl IntoIterator<Item = u32>) -> Claims {
+        sites
+            .into_iter()
+            .filter_map(|site| {
+                let index = self.table.iter().position(|id| *id == site)?;
+                Some((site, self.uuids[index]))
+            })
+            .collect()
+    }
+
+    fn varint(&mut self) -> Option<u64> {
+        let mut n = 0u64;
+        for (i, byte) in self.bytes.iter().enumerate().take(10) {
+            n |= ((byte & 0x7f) as u64) << (7 * i);
+
+            if byte & 0x80 == 0 {
+                self.bytes = &self.bytes[i + 1..];
+                return Some(n);
+            }
+        }
+
+        None
+    }
+
+    /// Only a batch that was read in its entirety is valid; and only then are its sites registered.
+    fn finish<I, T: Element>(self, items: I, storage: &mut Storage<T>) -> Option<I> {
+        if !self.bytes.is_empty() {
+            return None;
+        }
+
+        storage.register(self.uuids);
+        Some(items)
+    }
+}
+
+/// Maps signed integers onto unsigned ones so that small magnitudes stay small: 0, -1, 1, -2, 2…
+fn zigzag(n: i64) -> u64 {
+    ((n << 1) ^ (n >> 63)) as u64
+}
+
+fn unzigzag(n: u64) -> i64 {
+    (n >> 1) as i64 ^ -((n & 1) as i64)
+}
+
+#[cfg(test)]
+mod test {
+    use std::num::NonZeroU32;
+
+    use quickcheck::TestResult;
+    use quickcheck_macros::quickcheck;
+
+    use super::*;
+    use crate::Storage;
+
+    #[quickcheck]
+    fn position_round_trip(positions: Vec<(u8, u64, Vec<NonZeroU32>)>) -> TestResult {
+        let mut sites = SiteRegistry::default();
+        let ids: Vec<u32> = (1..=4).map(|uuid| sites.register(uuid)).collect();
+
+        let positions: Vec<(u32, u64, Vec<u32>)> = positions
+            .into_iter()
+            .map(|(site, clock, path)| {
+                let path = path.into_iter().map(NonZeroU32::get).collect();
+                (ids[site as usize % ids.len()], clock, path)
+            })
+            .collect();
+
+        // such a level zero — or an empty path, but for the first sentinel’s — is never generated,
+        // and is turned away by `decode_positions()`
+        if positions
+            .iter()
+            .any(|(_, _, path)| path.first().is_none_or(|n| *n >= Position::end_bound(0)))
+        {
+            return TestResult::discard();
+        }
+
+        let positions: Vec<Position> = positions
+            .iter()
+            .map(|(site, clock, path)| Position::new(*site, *clock, path))
+            .collect();
+
+        let bytes = encode_positions(&positions, &sites).unwrap();
+
+        let mut other = Storage::<char>::default();
+        let decoded = decode_positions(&bytes, &mut other).unwrap();
+
+        for (pos, decoded) in positions.iter().zip(&decoded) {
+            assert_eq!(pos.path(), decoded.path());
+            assert_eq!(pos.clock(), decoded.clock());
+            assert_eq!(
+                sites.uuid(pos.site_id()),
+                other.sites().uuid(decoded.site_id())
+            );
+        }
+
+        TestResult::from_bool(positions.len() == decoded.len())
+    }
+
+    #[test]
+    fn operations_round_trip() {
+        let mut storage = Storage::default();
+        let mut ops = storage.append("hello,\nworld".repeat(100).chars());
+
+        let pos = storage.characters(..).nth(3).map(|(pos, _)| pos.clone());
+        ops.extend(storage.remove(&pos.unwrap()));
+        ops.extend(storage.remove_range(10..20)); // sharing a single dot
+
+        let bytes = encode(&ops).unwrap();
+        assert!(bytes.len() < 6 * ops.len()); // a run shares nearly all of its path
+
+        let mut replica = Storage::default();
+        let decoded = decode(&bytes, &mut replica).unwrap();
+        for (op, decoded) in ops.iter().zip(&decoded) {
+            assert_eq!(op.dot().clock, decoded.dot().clock);
+            assert!(replica.apply(decoded));
+        }
+
+        assert_eq!(replica.string(..), storage.string(..));
+
+        // truncated input is rejected rather than misread
+        assert_eq!(decode(&bytes[..bytes.len() - 1], &mut replica), None);
+    }
+
+    #[test]
+    fn claimed_sites() {
+        let mut a = Storage::with_uuid(1);
+        let mut b = Storage::with_uuid(2);
+
+        let ops = a.append("hi".chars());
+        for op in &ops {
+            b.apply(op);
+        }
+
+        // b learnt a’s UUID from its operations, so can put the delete of its insert on the wire…
+        let mut delete = b.remove(ops[0].position()).unwrap();
+        let bytes = encode(std::slice::from_ref(&delete)).unwrap();
+        let decoded = decode(&bytes, &mut a).unwrap();
+        assert!(a.apply(&decoded[0]));
+        assert_eq!(a.string(..), b.string(..));
+
+        // …unlike an operation that doesn’t claim the UUIDs of the sites it mentions
+        let site = ops[0].position().site_id();
+        if let Operation::Delete { sites, .. } = &mut delete {
+            *sites = sites.iter().filter(|(id, _)| *id != site).collect();
+        }
+        assert_eq!(encode(&[delete]), Err(UnknownSite(site)));
+    }
+}